@@ -9,7 +9,7 @@
 
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex, MutexGuard};
 
 use failure::bail;
@@ -56,6 +56,7 @@ pub struct Catalog {
     indexes: HashMap<GlobalId, Vec<Vec<ScalarExpr>>>,
     ambient_schemas: BTreeMap<String, Schema>,
     storage: Arc<Mutex<sql::Connection>>,
+    path: Option<PathBuf>,
     serialize_item: fn(&CatalogItem) -> Vec<u8>,
 }
 
@@ -218,6 +219,7 @@ impl Catalog {
             indexes: HashMap::new(),
             ambient_schemas: BTreeMap::new(),
             storage: Arc::new(Mutex::new(storage)),
+            path: path.map(Path::to_path_buf),
             serialize_item: S::serialize,
         };
 
@@ -301,6 +303,16 @@ impl Catalog {
         self.storage.clone()
     }
 
+    /// Opens a new connection to the catalog's storage, so that its user does not
+    /// contend with the catalog for the shared connection. In-memory catalogs
+    /// cannot be reached from another connection, so the shared one is returned.
+    pub fn open_storage(&self) -> Result<Arc<Mutex<sql::Connection>>, failure::Error> {
+        match &self.path {
+            Some(path) => Ok(Arc::new(Mutex::new(sql::Connection::open(Some(path))?))),
+            None => Ok(self.storage_handle()),
+        }
+    }
+
     pub fn allocate_id(&mut self) -> Result<GlobalId, failure::Error> {
         self.storage().allocate_id()
     }
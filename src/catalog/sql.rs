@@ -8,6 +8,7 @@
 // by the Apache License, Version 2.0.
 
 use std::path::Path;
+use std::time::Duration;
 
 use failure::bail;
 use rusqlite::params;
@@ -49,10 +50,40 @@ CREATE TABLE timestamps (
     sid blob NOT NULL,
     vid blob NOT NULL,
     timestamp integer NOT NULL,
-    offset blob NOT NULL,
+    offset integer NOT NULL,
     PRIMARY KEY (sid, vid, timestamp)
 );
 
+CREATE TABLE consistency_offsets (
+    sid blob NOT NULL,
+    vid blob NOT NULL,
+    offset integer NOT NULL,
+    transaction_events integer NOT NULL,
+    PRIMARY KEY (sid, vid)
+);
+
+CREATE TABLE paused_sources (
+    sid blob NOT NULL,
+    vid blob NOT NULL,
+    PRIMARY KEY (sid, vid)
+);
+
+CREATE TABLE timestamp_epoch (
+    epoch integer NOT NULL
+);
+
+CREATE INDEX timestamps_offset ON timestamps (sid, vid, offset);
+
+CREATE TABLE checkpoints (
+    sid blob NOT NULL,
+    vid blob NOT NULL,
+    timestamp integer NOT NULL,
+    offset integer NOT NULL,
+    PRIMARY KEY (sid, vid, timestamp)
+);
+
+CREATE INDEX checkpoints_offset ON checkpoints (sid, vid, offset);
+
 INSERT INTO gid_alloc VALUES (1);
 INSERT INTO databases VALUES (1, 'materialize');
 INSERT INTO schemas VALUES
@@ -61,6 +92,87 @@ INSERT INTO schemas VALUES
     (3, 1, 'public');
 ";
 
+/// Changes to catalogs that were persisted before `SCHEMA` took its current form, in the
+/// order they were introduced. The `user_version` of a catalog counts the migrations that
+/// were applied to it, and catalogs created from `SCHEMA` start out with all of them.
+/// Migrations must only ever be appended.
+const MIGRATIONS: &[&str] = &[
+    "
+-- Tables and indexes that were added after catalogs were first persisted
+CREATE TABLE IF NOT EXISTS consistency_offsets (
+    sid blob NOT NULL,
+    vid blob NOT NULL,
+    offset integer NOT NULL,
+    PRIMARY KEY (sid, vid)
+);
+
+CREATE TABLE IF NOT EXISTS paused_sources (
+    sid blob NOT NULL,
+    vid blob NOT NULL,
+    PRIMARY KEY (sid, vid)
+);
+
+CREATE TABLE IF NOT EXISTS timestamp_epoch (
+    epoch integer NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS timestamps_offset ON timestamps (sid, vid, offset);
+
+CREATE TABLE IF NOT EXISTS checkpoints (
+    sid blob NOT NULL,
+    vid blob NOT NULL,
+    timestamp integer NOT NULL,
+    offset integer NOT NULL,
+    PRIMARY KEY (sid, vid)
+);
+
+-- Timestamps, offsets and epochs used to be stored as JSON blobs, which SQLite neither
+-- orders nor compares numerically
+UPDATE timestamps SET timestamp = CAST(CAST(timestamp AS text) AS integer)
+    WHERE typeof(timestamp) = 'blob';
+UPDATE timestamps SET offset = CAST(CAST(offset AS text) AS integer)
+    WHERE typeof(offset) = 'blob';
+UPDATE checkpoints SET timestamp = CAST(CAST(timestamp AS text) AS integer)
+    WHERE typeof(timestamp) = 'blob';
+UPDATE checkpoints SET offset = CAST(CAST(offset AS text) AS integer)
+    WHERE typeof(offset) = 'blob';
+UPDATE consistency_offsets SET offset = CAST(CAST(offset AS text) AS integer)
+    WHERE typeof(offset) = 'blob';
+UPDATE timestamp_epoch SET epoch = CAST(CAST(epoch AS text) AS integer)
+    WHERE typeof(epoch) = 'blob';
+",
+    "
+-- Checkpoints used to hold a single row per source
+ALTER TABLE checkpoints RENAME TO old_checkpoints;
+
+CREATE TABLE checkpoints (
+    sid blob NOT NULL,
+    vid blob NOT NULL,
+    timestamp integer NOT NULL,
+    offset integer NOT NULL,
+    PRIMARY KEY (sid, vid, timestamp)
+);
+
+INSERT INTO checkpoints SELECT sid, vid, timestamp, offset FROM old_checkpoints;
+
+DROP TABLE old_checkpoints;
+
+CREATE INDEX checkpoints_offset ON checkpoints (sid, vid, offset);
+",
+    "
+-- The number of events counted in Debezium transaction records used to be derived from
+-- the persisted timestamps
+ALTER TABLE consistency_offsets ADD COLUMN transaction_events integer NOT NULL DEFAULT 0;
+
+UPDATE consistency_offsets SET transaction_events = coalesce((
+    SELECT max(offset) + 1 FROM (
+        SELECT offset FROM timestamps
+            WHERE sid = consistency_offsets.sid AND vid = consistency_offsets.vid
+        UNION ALL SELECT offset FROM checkpoints
+            WHERE sid = consistency_offsets.sid AND vid = consistency_offsets.vid)), 0);
+",
+];
+
 #[derive(Debug)]
 pub struct Connection {
     inner: rusqlite::Connection,
@@ -69,24 +181,48 @@ pub struct Connection {
 impl Connection {
     pub fn open(path: Option<&Path>) -> Result<Connection, failure::Error> {
         let mut sqlite = match path {
-            Some(path) => rusqlite::Connection::open(path)?,
+            Some(path) => {
+                let sqlite = rusqlite::Connection::open(path)?;
+                // Several connections may be open on the same catalog. With a
+                // write-ahead log, readers do not block the writer, and writers
+                // wait for each other rather than failing.
+                let _: String =
+                    sqlite.query_row("PRAGMA journal_mode = WAL", params![], |row| row.get(0))?;
+                sqlite.busy_timeout(Duration::from_secs(5))?;
+                sqlite
+            }
             None => rusqlite::Connection::open_in_memory()?,
         };
         let tx = sqlite.transaction()?;
         let app_id: i32 = tx.query_row("PRAGMA application_id", params![], |row| row.get(0))?;
-        if app_id == 0 {
+        let version = if app_id == 0 {
             tx.execute(
                 &format!("PRAGMA application_id = {}", APPLICATION_ID),
                 params![],
             )?;
             // Create the on-disk schema, since it doesn't already exist.
             tx.execute_batch(&SCHEMA)?;
-            true
+            MIGRATIONS.len()
         } else if app_id == APPLICATION_ID {
-            false
+            let version: i32 = tx.query_row("PRAGMA user_version", params![], |row| row.get(0))?;
+            version as usize
         } else {
             bail!("incorrect application_id in catalog");
         };
+        if version > MIGRATIONS.len() {
+            bail!(
+                "catalog version {} is newer than the supported version {}",
+                version,
+                MIGRATIONS.len()
+            );
+        }
+        for migration in &MIGRATIONS[version..] {
+            tx.execute_batch(migration)?;
+        }
+        tx.execute(
+            &format!("PRAGMA user_version = {}", MIGRATIONS.len()),
+            params![],
+        )?;
         tx.commit()?;
 
         Ok(Connection { inner: sqlite })
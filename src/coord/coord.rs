@@ -29,6 +29,7 @@ use futures::future::FutureExt;
 use futures::future::{self, TryFutureExt};
 use futures::sink::SinkExt;
 use futures::stream::{self, StreamExt, TryStreamExt};
+use log::{error, info};
 use timely::progress::frontier::{Antichain, AntichainRef, MutableAntichain};
 use timely::progress::ChangeBatch;
 
@@ -51,7 +52,7 @@ use sql::{MutationKind, ObjectType, Plan, Session};
 use sql::{Params, PreparedStatement};
 
 use crate::persistence::SqlSerializer;
-use crate::timestamp::{TimestampChannel, TimestampConfig, TimestampMessage, Timestamper};
+use crate::timestamp::{self, TimestampChannel, TimestampConfig, TimestampMessage, Timestamper};
 use crate::{Command, ExecuteResponse, Response, StartupMessage};
 
 type ClientTx = futures::channel::oneshot::Sender<Response<ExecuteResponse>>;
@@ -110,6 +111,9 @@ where
     C: comm::Connection,
 {
     pub fn new(config: Config<C>) -> Result<Self, failure::Error> {
+        if let Some(timestamp_config) = &config.timestamp {
+            timestamp_config.validate()?;
+        }
         let mut broadcast_tx = config.switchboard.broadcast_tx(dataflow::BroadcastToken);
 
         let symbiosis = if let Some(symbiosis_url) = config.symbiosis_url {
@@ -236,6 +240,9 @@ where
         } else {
             Catalog::open::<SqlSerializer, _>(catalog_path, |_| ())?
         };
+        if let Some(timestamp_config) = &config.timestamp {
+            timestamp::check_persisted_epoch(timestamp_config, &catalog.storage_handle())?;
+        }
 
         let executor = config.executor;
         executor.enter(move || {
@@ -346,14 +353,24 @@ where
         let (source_tx, source_rx) = std::sync::mpsc::channel();
         let (ts_tx, ts_rx) = std::sync::mpsc::channel();
         let _timestamper_thread = if let Some(config) = &self.timestamp_config {
+            let storage = if config.dedicated_storage {
+                self.catalog
+                    .open_storage()
+                    .expect("Failed to open timestamper storage")
+            } else {
+                self.catalog.storage_handle()
+            };
+            // The epoch was checked against the persisted timestamps when the coordinator
+            // was created
             let mut timestamper = Timestamper::new(
                 config,
-                self.catalog.storage_handle(),
+                storage,
                 TimestampChannel {
                     sender: source_tx,
                     receiver: ts_rx,
                 },
-            );
+            )
+            .expect("Failed to create timestamper");
             Some(thread::spawn(move || timestamper.update()).join_on_drop())
         } else {
             None
@@ -401,6 +418,22 @@ where
                                     },
                                 );
                             },
+                            TimestampMessage::CaughtUp(id, caught_up) => {
+                                if caught_up {
+                                    info!("Source {} caught up with its Kafka high watermark", id);
+                                } else {
+                                    info!("Source {} fell behind its Kafka high watermark", id);
+                                }
+                            }
+                            TimestampMessage::Complete(id) => {
+                                info!("Source {} has been fully timestamped", id);
+                            }
+                            TimestampMessage::Live(id) => {
+                                info!("Source {} has finished recovery and is live", id);
+                            }
+                            TimestampMessage::AddRejected(id, reason) => {
+                                error!("Source {} will not be timestamped: {}", id, reason);
+                            }
                             _ => {}
                         }
                     }
@@ -422,6 +455,10 @@ where
                             conn_id,
                             tx,
                         }) => {
+                            if self.timestamp_config.as_ref().map_or(false, |config| config.pull) {
+                                // Sources only advance when queries need them to
+                                let _ = ts_tx.send(TimestampMessage::Tick);
+                            }
                             let result = self.handle_begin_execute(session, portal_name, tx);
                             match result {
                                 MaybeFuture::Immediate(val) => {
@@ -502,7 +539,7 @@ where
                                             worker_id: _,
                                             message: WorkerFeedback::CreateSource(source_id,ksc,consistency)}) => {
                             ts_tx
-                                .send(TimestampMessage::Add(source_id, ksc.url, ksc.topic, ksc.ssl_certificate_file, consistency))
+                                .send(TimestampMessage::Add(source_id, ksc, consistency))
                                 .expect("Failed to send CREATE Instance notice to timestamper");
                         }
                     }
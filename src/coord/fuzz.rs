@@ -0,0 +1,79 @@
+// Copyright Materialize, Inc. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! Fuzz testing of the consistency record parser.
+
+use crate::timestamp::byo_parse_ts_update;
+
+/// Parses `payload` as a consistency record. Accepted records must round-trip through
+/// their CSV representation.
+pub fn fuzz(payload: &[u8]) {
+    if let Ok((source_name, ts, offset)) = byo_parse_ts_update(payload) {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer
+            .write_record(&[source_name.clone(), ts.to_string(), offset.to_string()])
+            .unwrap();
+        let record = writer.into_inner().unwrap();
+        let reparsed = byo_parse_ts_update(&record).unwrap();
+        assert_eq!(reparsed, (source_name, ts, offset));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn embedded_commas() {
+        assert!(byo_parse_ts_update(b"my,source,1,2").is_err());
+        assert_eq!(
+            byo_parse_ts_update(b"\"my,source\",1,2").unwrap(),
+            ("my,source".into(), 1, 2)
+        );
+        fuzz(b"my,source,1,2");
+        fuzz(b"\"my,source\",1,2");
+    }
+
+    #[test]
+    fn overflow() {
+        assert!(byo_parse_ts_update(b"source,18446744073709551616,2").is_err());
+        assert!(byo_parse_ts_update(b"source,1,9223372036854775808").is_err());
+        assert!(byo_parse_ts_update(b"source,-1,2").is_err());
+        fuzz(b"source,18446744073709551616,9223372036854775808");
+    }
+
+    #[test]
+    fn non_utf8() {
+        assert!(byo_parse_ts_update(b"source\xff,1,2").is_err());
+        fuzz(b"\xff\xfe,1,2");
+    }
+
+    #[test]
+    fn whitespace() {
+        assert_eq!(
+            byo_parse_ts_update(b"source,1,2\n").unwrap(),
+            ("source".into(), 1, 2)
+        );
+        assert_eq!(
+            byo_parse_ts_update(b" source , 1 , 2 \r\n").unwrap(),
+            ("source".into(), 1, 2)
+        );
+        assert!(byo_parse_ts_update(b"source,1,2\nsource,3,4").is_err());
+        assert!(byo_parse_ts_update(b"").is_err());
+    }
+
+    #[test]
+    fn valid() {
+        assert_eq!(
+            byo_parse_ts_update(b"source,18446744073709551615,-1").unwrap(),
+            ("source".into(), u64::max_value(), -1)
+        );
+        fuzz(b"source,1,2");
+    }
+}
@@ -25,6 +25,12 @@ mod coord;
 mod persistence;
 mod timestamp;
 
+pub mod fuzz;
+
 pub use self::coord::{dump_catalog, Config, Coordinator};
-pub use self::timestamp::TimestampConfig;
+pub use self::timestamp::{
+    ExportedTimestamp, RtSourceProgress, SourceTimestampUpdate, TimestampAuditConfig,
+    TimestampChannel, TimestampConfig, TimestampConfigBuilder, TimestampMessage, TimestampRow,
+    TimestampSink, Timestamper, TimestamperObserver, ValidationReport,
+};
 pub use command::{Command, ExecuteResponse, Response, RowsFuture, StartupMessage};
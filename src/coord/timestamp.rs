@@ -7,511 +7,5261 @@
 // the Business Source License, use of this software will be governed
 // by the Apache License, Version 2.0.
 
-use rusqlite::{params, NO_PARAMS};
+use rusqlite::{params, ToSql, NO_PARAMS};
 
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, Read, Write};
+use std::path::{Path, PathBuf};
 use std::str;
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{Arc, Mutex, MutexGuard};
-use std::thread;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use catalog::sql::SqlVal;
-use expr::SourceInstanceId;
+use expr::{GlobalId, SourceInstanceId};
 
 use rdkafka::consumer::{BaseConsumer, Consumer};
-use rdkafka::message::Message;
-use rdkafka::ClientConfig;
+use rdkafka::error::{KafkaError, KafkaResult};
+use rdkafka::message::{BorrowedMessage, Headers, Message};
+use rdkafka::producer::{BaseProducer, BaseRecord};
+use rdkafka::{ClientConfig, Offset, TopicPartitionList};
 
-use dataflow_types::Consistency;
+use dataflow_types::{
+    Consistency, ConsistencyCompression, ConsistencyFormat, CsvConsistencyColumns,
+    JsonConsistencyFields, KafkaSourceConnector, PROTECTED_KAFKA_OPTIONS,
+};
 
-use log::{error, info};
+use failure::{bail, format_err};
+use futures::Stream;
+use lazy_static::lazy_static;
+use log::{debug, error, info, log, warn, Level};
+use prometheus::{register_int_gauge_vec, IntGaugeVec};
+use serde::{Deserialize, Serialize};
 use url::Url;
 
-pub struct TimestampConfig {
-    pub frequency: Duration,
-    pub max_size: i64,
+lazy_static! {
+    static ref SKIPPED_PASSES: IntGaugeVec = register_int_gauge_vec!(
+        "mz_timestamper_consecutive_skipped_passes",
+        "the number of consecutive timestamping passes in which a source was skipped",
+        &["source_id"]
+    )
+    .unwrap();
+    static ref STALLED_PRODUCERS: IntGaugeVec = register_int_gauge_vec!(
+        "mz_timestamper_consistency_producer_stalled",
+        "whether no record has been read from the consistency topic of a source for longer than the stall threshold",
+        &["source_id"]
+    )
+    .unwrap();
 }
 
-#[derive(Debug)]
-pub enum TimestampMessage {
-    Add(SourceInstanceId, Url, String, Option<PathBuf>, Consistency),
-    DropInstance(SourceInstanceId),
-    BatchedUpdate(u64, Vec<(SourceInstanceId, i64)>),
-    Update(SourceInstanceId, u64, i64),
-    Shutdown,
-}
+/// Number of consecutive Kafka failures after which a consumer is recreated
+const MAX_CONSECUTIVE_FAILURES: u32 = 5;
 
-pub struct TimestampChannel {
-    pub sender: std::sync::mpsc::Sender<TimestampMessage>,
-    pub receiver: std::sync::mpsc::Receiver<TimestampMessage>,
-}
+/// Minimum interval between two reports of rejected consistency records for a source
+const REJECTION_LOG_INTERVAL: Duration = Duration::from_secs(60);
 
-/// Timestamp consumer: wrapper around Kafka consumer that stores necessary information
-/// about topics and offset for real-time consistency
-struct RtTimestampConsumer {
-    consumer: BaseConsumer,
-    topic: String,
-    last_offset: i64,
+/// Interval at which progress is logged while recovering a source's timestamp history
+const RECOVERY_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Maximum number of threads used to query the watermarks of RT sources
+const RT_QUERY_THREADS: usize = 4;
+
+/// Fraction of the timestamp frequency that a single pass may spend reading consistency topics
+const BYO_QUERY_BUDGET_FRACTION: u32 = 2;
+
+/// Maximum time to wait for the next record of a consistency topic
+const CONSISTENCY_POLL_TIMEOUT: Duration = Duration::from_millis(60);
+
+/// Number of attempts at subscribing to a consistency topic before giving up
+const SUBSCRIBE_ATTEMPTS: u32 = 3;
+
+/// Interval between two attempts at subscribing to a consistency topic, multiplied by the
+/// number of failed attempts
+const SUBSCRIBE_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Distance to the timestamp ceiling, in milliseconds, below which a warning is logged
+const TIMESTAMP_CEILING_WARNING_MARGIN: u64 = 7 * 24 * 60 * 60 * 1000;
+
+/// Number of consecutive skipped passes after which a source is considered broken
+const MAX_SKIPPED_PASSES: u32 = 1000;
+
+/// Interval at which a broken source is polled to check whether it has recovered
+const BROKEN_SOURCE_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Reads the persisted timestamp history of a source, from both its checkpoint and the
+/// timestamps persisted since, in timestamp order
+const HISTORY_QUERY: &str =
+    "SELECT timestamp, offset FROM checkpoints WHERE sid = ?1 AND vid = ?2 \
+     UNION ALL SELECT timestamp, offset FROM timestamps WHERE sid = ?1 AND vid = ?2 \
+     ORDER BY timestamp";
+
+/// Reads the earliest persisted timestamp of a source whose offset is at least the given
+/// one, from both its checkpoint and the timestamps persisted since
+const OFFSET_LOOKUP_QUERY: &str =
+    "SELECT min(timestamp) FROM ( \
+     SELECT * FROM ( \
+     SELECT timestamp FROM checkpoints WHERE sid = ?1 AND vid = ?2 AND offset >= ?3 \
+     ORDER BY offset, timestamp LIMIT 1) \
+     UNION ALL SELECT * FROM ( \
+     SELECT timestamp FROM timestamps WHERE sid = ?1 AND vid = ?2 AND offset >= ?3 \
+     ORDER BY offset, timestamp LIMIT 1))";
+
+#[derive(Clone, Debug)]
+pub struct TimestampConfig {
+    pub frequency: Duration,
+    pub max_size: i64,
+    /// Whether to log key timestamping events as JSON lines rather than free-form text
+    pub structured_logging: bool,
+    /// Prefix of the Kafka group id of RT consumers. The topic and source id are appended
+    pub rt_group_id_prefix: String,
+    /// Prefix of the Kafka group id of BYO consumers. The topic and source id are appended
+    pub byo_group_id_prefix: String,
+    /// Maximum time to wait for the watermarks of a topic when timestamping
+    pub watermark_timeout: Duration,
+    /// Whether the timestamper opens its own connection to the catalog, rather than
+    /// sharing the coordinator's
+    pub dedicated_storage: bool,
+    /// Whether timestamping passes only run when requested with a `Tick`, rather than
+    /// every `frequency`
+    pub pull: bool,
+    /// The Kafka topic to which every timestamp update sent to the coordinator is
+    /// mirrored, if any
+    pub audit: Option<TimestampAuditConfig>,
+    /// Whether a RT pass that runs before the clock has advanced reuses the previous
+    /// timestamp, rather than waiting for a greater one
+    pub coalesce_timestamps: bool,
+    /// The rack of the Kafka consumers, which lets brokers serve fetches from the closest
+    /// replica
+    pub client_rack: Option<String>,
+    /// Maximum number of sources that are timestamped at once, if any
+    pub max_sources: Option<usize>,
+    /// The epoch of real-time timestamps, as an offset from the Unix epoch. It must not
+    /// change once timestamps have been persisted
+    pub epoch: Duration,
+    /// Time without any record on the consistency topic of a BYO source after which its
+    /// producer is reported as stalled
+    pub byo_stall_threshold: Duration,
+    /// Time after a source is added during which it is not reported as stalled, as
+    /// recovering many sources at startup can take a while
+    pub startup_grace_period: Duration,
+    /// Number of consecutive empty polls of the consistency topic of a BYO source after
+    /// which it is reported as idle, if any. Unlike a stall, an idle source is expected,
+    /// so it is only reported once until records flow again
+    pub byo_idle_cycles: Option<u32>,
+    /// Number of raw consistency records retained for each BYO source, so that they can
+    /// be inspected when debugging format mismatches. Zero disables the retention
+    pub byo_record_history: usize,
+    /// Minimum time between two writes of assigned timestamps to storage, if any. Timestamps
+    /// are still sent to the coordinator on every pass. Those that were not written yet are
+    /// lost on a crash, and the messages they covered are timestamped again after the
+    /// restart, after the last persisted timestamps
+    pub persist_interval: Option<Duration>,
+    /// Number of timestamping passes between two checkpoints, if any. A checkpoint drops
+    /// the persisted timestamps of each source that do not determine the timestamp of any
+    /// message, such as those of passes without new messages, which speeds up recovery
+    pub checkpoint_interval: Option<u32>,
+    /// Whether recovery only sends the latest persisted timestamp of each source, rather
+    /// than its full history. All recovered messages are then assigned that timestamp
+    pub fast_recovery: bool,
+    /// Largest real-time timestamp that is assigned. Real-time sources stop advancing once
+    /// it is reached, until an operator raises it
+    pub timestamp_ceiling: u64,
+    /// Whether the timestamper starts as a standby, which tracks sources but neither
+    /// persists nor sends timestamps until it receives a `TimestampMessage::Promote`
+    pub standby: bool,
 }
 
-struct ByoTimestampConsumer {
-    consumer: BaseConsumer,
-    topic: String,
-    timestamp_topic: String,
+/// The Kafka topic to which timestamp updates are mirrored for auditing
+#[derive(Clone, Debug)]
+pub struct TimestampAuditConfig {
+    pub url: Url,
+    pub topic: String,
+    /// The certificate used to connect to the cluster of the topic, if any
+    pub ssl_certificate_file: Option<PathBuf>,
+    /// Additional rdkafka properties of the producer
+    pub config_options: HashMap<String, String>,
 }
 
-fn byo_query_source(consumer: &mut ByoTimestampConsumer, max_increment_size: i64) -> Vec<Vec<u8>> {
-    let mut messages = vec![];
-    let mut msg_count = 0;
-    while let Some(payload) = get_next_message(consumer) {
-        messages.push(payload);
-        msg_count += 1;
-        if msg_count == max_increment_size {
-            // Make sure to bound the number of timestamp updates we have at once,
-            // to avoid overflowing the system
-            break;
+impl TimestampAuditConfig {
+    /// Returns the configuration of an audit topic without a certificate or additional
+    /// rdkafka properties
+    pub fn new(url: Url, topic: impl Into<String>) -> Self {
+        TimestampAuditConfig {
+            url,
+            topic: topic.into(),
+            ssl_certificate_file: None,
+            config_options: HashMap::new(),
         }
     }
-    messages
 }
 
-fn byo_extract_ts_update(
-    consumer: &ByoTimestampConsumer,
-    messages: Vec<Vec<u8>>,
-) -> Vec<(u64, i64)> {
-    let mut updates = vec![];
-    for payload in messages {
-        let st = str::from_utf8(&payload);
-        match st {
-            Ok(timestamp) => {
-                // Extract timestamp from payload
-                let split: Vec<&str> = timestamp.split(',').collect();
-                if split.len() != 3 {
-                    error!("incorrect payload format. Expected: SourceName/TS/Offset");
-                    continue;
-                }
-                let topic_name = String::from(split[0]);
-                let ts = match split[1].parse::<u64>() {
-                    Ok(i) => i,
-                    Err(err) => {
-                        error!("incorrect timestamp format {}", err);
-                        continue;
-                    }
-                };
-                let offset = match split[2].parse::<i64>() {
-                    Ok(i) => i,
-                    Err(err) => {
-                        error!("incorrect timestamp format {}", err);
-                        continue;
-                    }
-                };
-                if topic_name == consumer.topic {
-                    updates.push((ts, offset))
-                }
-            }
-            Err(err) => error!("incorrect payload format: {}", err),
+impl Default for TimestampConfig {
+    fn default() -> Self {
+        TimestampConfig {
+            frequency: Duration::from_millis(10),
+            max_size: 10000,
+            structured_logging: false,
+            rt_group_id_prefix: "materialize-rt".into(),
+            byo_group_id_prefix: "materialize-byo".into(),
+            watermark_timeout: Duration::from_secs(1),
+            dedicated_storage: false,
+            pull: false,
+            audit: None,
+            coalesce_timestamps: false,
+            client_rack: None,
+            max_sources: None,
+            epoch: Duration::from_secs(0),
+            byo_stall_threshold: Duration::from_secs(300),
+            byo_idle_cycles: None,
+            byo_record_history: 0,
+            startup_grace_period: Duration::from_secs(0),
+            persist_interval: None,
+            checkpoint_interval: None,
+            fast_recovery: false,
+            // u64::MAX is rejected as a sentinel in consistency records
+            timestamp_ceiling: u64::max_value() - 1,
+            standby: false,
         }
     }
-    updates
 }
 
-fn byo_notify_coordinator(
-    id: SourceInstanceId,
-    updates: Vec<(u64, i64)>,
-    coord_channel: &TimestampChannel,
-) {
-    for (ts, offset) in updates {
-        coord_channel
-            .sender
-            .send(TimestampMessage::Update(id, ts, offset))
-            .expect("Failed to send update to coordinator");
+impl TimestampConfig {
+    /// Returns a builder for a `TimestampConfig`, initialized with the default values.
+    pub fn builder() -> TimestampConfigBuilder {
+        TimestampConfigBuilder::default()
     }
-}
 
-/// Polls a message from a Kafka Source
-fn get_next_message(consumer: &mut ByoTimestampConsumer) -> Option<Vec<u8>> {
-    if let Some(result) = consumer.consumer.poll(Duration::from_millis(60)) {
-        match result {
-            Ok(message) => match message.payload() {
-                Some(p) => Some(p.to_vec()),
-                None => {
-                    error!("unexpected null payload");
-                    None
-                }
-            },
-            Err(err) => {
-                error!("Failed to process message {}", err);
-                None
-            }
+    /// Checks that the configuration can be used to timestamp sources. A `max_size` that is
+    /// not positive would prevent real-time sources from ever advancing, and so would an
+    /// epoch that the clock has not reached yet
+    pub fn validate(&self) -> Result<(), failure::Error> {
+        if self.max_size <= 0 {
+            bail!("max_size must be positive, got {}", self.max_size);
         }
-    } else {
-        None
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        if self.epoch >= now {
+            bail!(
+                "epoch must be in the past, got {} ms since the Unix epoch",
+                self.epoch.as_millis()
+            );
+        }
+        Ok(())
     }
 }
 
-pub struct Timestamper {
-    // Current list of up to date sources that use a real time consistency model
-    rt_sources: HashMap<SourceInstanceId, RtTimestampConsumer>,
+/// A builder for a [`TimestampConfig`].
+#[derive(Clone, Debug, Default)]
+pub struct TimestampConfigBuilder {
+    config: TimestampConfig,
+}
 
-    // Current list of up to date sources that use a BYO consistency model
-    byo_sources: HashMap<SourceInstanceId, ByoTimestampConsumer>,
+impl TimestampConfigBuilder {
+    /// Sets the interval at which sources are timestamped.
+    pub fn frequency(mut self, frequency: Duration) -> Self {
+        self.config.frequency = frequency;
+        self
+    }
 
-    // Connection to the underlying SQL lite instance
-    storage: Arc<Mutex<catalog::sql::Connection>>,
+    /// Sets the maximum number of messages that can be assigned the same timestamp.
+    pub fn max_size(mut self, max_size: i64) -> Self {
+        self.config.max_size = max_size;
+        self
+    }
 
-    // Channel with coordinator
-    coord_channel: TimestampChannel,
+    /// Sets whether key timestamping events are logged as JSON lines.
+    pub fn structured_logging(mut self, structured_logging: bool) -> Self {
+        self.config.structured_logging = structured_logging;
+        self
+    }
 
-    // Last Timestamp (necessary because not necessarily increasing otherwise)
-    current_timestamp: u64,
+    /// Sets the prefix of the Kafka group id of RT consumers.
+    pub fn rt_group_id_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.config.rt_group_id_prefix = prefix.into();
+        self
+    }
 
-    // Frequency at which thread should run
-    timestamp_frequency: Duration,
+    /// Sets the prefix of the Kafka group id of BYO consumers.
+    pub fn byo_group_id_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.config.byo_group_id_prefix = prefix.into();
+        self
+    }
 
-    // Max increment size
-    max_increment_size: i64,
-}
+    /// Sets the maximum time to wait for the watermarks of a topic when timestamping.
+    pub fn watermark_timeout(mut self, watermark_timeout: Duration) -> Self {
+        self.config.watermark_timeout = watermark_timeout;
+        self
+    }
 
-impl Timestamper {
-    pub fn new(
-        config: &TimestampConfig,
-        storage: Arc<Mutex<catalog::sql::Connection>>,
-        channel: TimestampChannel,
-    ) -> Self {
-        // Recover existing data by running max on the timestamp count. This will ensure that
-        // there will never be two duplicate entries and that there is a continuous stream
-        // of timestamp updates across reboots
-        let max_ts = storage
-            .lock()
-            .expect("lock poisoned")
-            .prepare("SELECT MAX(timestamp) FROM timestamps")
-            .expect("Failed to prepare statement")
-            .query_row(NO_PARAMS, |row| {
-                let res: Result<SqlVal<u64>, _> = row.get(2);
-                match res {
-                    Ok(res) => Ok(res.0),
-                    _ => Ok(0),
-                }
-            })
-            .expect("Failure to parse timestamp");
+    /// Sets whether the timestamper opens its own connection to the catalog.
+    pub fn dedicated_storage(mut self, dedicated_storage: bool) -> Self {
+        self.config.dedicated_storage = dedicated_storage;
+        self
+    }
 
-        info!(
-            "Starting Timestamping Thread. Frequency: {} ms.",
-            config.frequency.as_millis()
-        );
+    /// Sets whether timestamping passes only run when requested with a `Tick`.
+    pub fn pull(mut self, pull: bool) -> Self {
+        self.config.pull = pull;
+        self
+    }
 
-        Self {
-            rt_sources: HashMap::new(),
-            byo_sources: HashMap::new(),
-            storage,
-            coord_channel: channel,
-            current_timestamp: max_ts,
-            timestamp_frequency: config.frequency,
-            max_increment_size: config.max_size,
-        }
+    /// Sets the Kafka topic to which timestamp updates are mirrored.
+    pub fn audit(mut self, audit: TimestampAuditConfig) -> Self {
+        self.config.audit = Some(audit);
+        self
     }
 
-    fn storage(&self) -> MutexGuard<catalog::sql::Connection> {
-        self.storage.lock().expect("lock poisoned")
+    /// Sets whether RT passes may reuse the previous timestamp.
+    pub fn coalesce_timestamps(mut self, coalesce_timestamps: bool) -> Self {
+        self.config.coalesce_timestamps = coalesce_timestamps;
+        self
     }
 
-    /// Run the update function in a loop at the specified frequency. Acquires timestamps using
-    /// either 1) the Kafka topic ground truth 2) real-time
-    pub fn update(&mut self) {
-        loop {
-            thread::sleep(self.timestamp_frequency);
-            let shutdown = self.update_sources();
-            if shutdown {
-                break;
-            } else {
-                self.update_rt_timestamp();
-                self.update_byo_timestamp();
-            }
-        }
+    /// Sets the rack of the Kafka consumers.
+    pub fn client_rack(mut self, client_rack: impl Into<String>) -> Self {
+        self.config.client_rack = Some(client_rack.into());
+        self
     }
 
-    /// Implements the real-time timestamping logic
-    fn update_rt_timestamp(&mut self) {
-        let watermarks = self.rt_query_sources();
-        self.rt_generate_next_timestamp();
-        self.rt_persist_timestamp(&watermarks);
-        self.rt_notify_coordinator(watermarks);
+    /// Sets the maximum number of sources that are timestamped at once.
+    pub fn max_sources(mut self, max_sources: usize) -> Self {
+        self.config.max_sources = Some(max_sources);
+        self
     }
 
-    /// Updates list of timestamp sources based on coordinator information. If using
-    /// using the real-time timestamping logic, then maintain a list of Kafka consumers
-    /// that poll topics to check how much data has been generated. If using the Kafka
-    /// source timestamping logic, then keep a mapping of (name,id) to translate user-
-    /// defined timestamps to GlobalIds
-    fn update_sources(&mut self) -> bool {
-        // First check if there are some new source that we should
-        // start checking
-        while let Ok(update) = self.coord_channel.receiver.try_recv() {
-            match update {
-                TimestampMessage::Add(id, url, topic, ssl_certificate_file, consistency) => {
-                    if !self.rt_sources.contains_key(&id) && !self.byo_sources.contains_key(&id) {
-                        // Did not know about source, must update
-                        match consistency {
-                            Consistency::RealTime => {
-                                info!("Timestamping Source {} with Real Time Consistency", id);
-                                let last_offset = self.rt_recover_source(id);
-                                let connector = self.create_rt_connector(
-                                    id,
-                                    url,
-                                    topic,
-                                    ssl_certificate_file,
-                                    last_offset,
-                                );
-                                self.rt_sources.insert(id, connector);
-                            }
-                            Consistency::BringYourOwn(consistency_topic) => {
-                                info!("Timestamping Source {} with BYO Consistency. Topic: {}, Consistency Topic: {}", id, topic, consistency_topic);
-                                let consumer = self.create_byo_connector(
-                                    url,
-                                    topic,
-                                    id,
-                                    ssl_certificate_file,
-                                    consistency_topic,
-                                );
-                                self.byo_sources.insert(id, consumer);
-                            }
-                        }
-                    }
-                }
-                TimestampMessage::DropInstance(id) => {
-                    info!("Dropping Timestamping for Source {}", id);
-                    self.storage()
-                        .prepare_cached("DELETE FROM timestamps WHERE sid = ? AND vid = ?")
-                        .expect("Failed to prepare delete statement")
-                        .execute(params![SqlVal(&id.sid), SqlVal(&id.vid)])
-                        .expect("Failed to execute delete statement");
-                    self.rt_sources.remove(&id);
-                    self.byo_sources.remove(&id);
-                }
-                TimestampMessage::Shutdown => return true,
-                _ => {
-                    // this should never happen
-                }
-            }
-        }
-        false
+    /// Sets the epoch of real-time timestamps, as an offset from the Unix epoch.
+    pub fn epoch(mut self, epoch: Duration) -> Self {
+        self.config.epoch = epoch;
+        self
     }
 
-    /// Implements the byo timestamping logic
-    fn update_byo_timestamp(&mut self) {
-        for (id, byo_consumer) in &mut self.byo_sources {
-            // Get the next set of messages from the Consistency topic
-            let messages = byo_query_source(byo_consumer, self.max_increment_size);
-            // Extract the timestamp updates for this topic only
-            let ts_updates = byo_extract_ts_update(byo_consumer, messages);
-            // Notify coordinator of updates
-            byo_notify_coordinator(id.clone(), ts_updates, &self.coord_channel);
-        }
+    /// Sets the time without consistency records after which a BYO producer is reported
+    /// as stalled.
+    pub fn byo_stall_threshold(mut self, byo_stall_threshold: Duration) -> Self {
+        self.config.byo_stall_threshold = byo_stall_threshold;
+        self
     }
 
-    /// Creates a RT Kafka connector
-    fn create_rt_connector(
-        &self,
-        id: SourceInstanceId,
-        url: Url,
-        topic: String,
-        ssl_certificate_file: Option<PathBuf>,
-        last_offset: i64,
-    ) -> RtTimestampConsumer {
-        let mut config = ClientConfig::new();
-        config
-            .set("auto.offset.reset", "smallest")
-            .set("group.id", &format!("materialize-rt-{}-{}", &topic, id))
-            .set("enable.auto.commit", "false")
-            .set("enable.partition.eof", "false")
-            .set("auto.offset.reset", "earliest")
-            .set("session.timeout.ms", "300000")
-            .set("max.poll.interval.ms", "300000") // 5 minutes
-            .set("fetch.message.max.bytes", "134217728")
-            .set("enable.sparse.connections", "true")
-            .set("bootstrap.servers", &url.to_string());
-
-        if let Some(path) = ssl_certificate_file {
-            config.set("security.protocol", "ssl");
-            config.set(
-                "ssl.ca.location",
-                path.to_str()
-                    .expect("Converting ssl certificate file path failed"),
-            );
-        }
+    /// Sets the number of consecutive empty polls after which a BYO source is reported as
+    /// idle.
+    pub fn byo_idle_cycles(mut self, byo_idle_cycles: u32) -> Self {
+        self.config.byo_idle_cycles = Some(byo_idle_cycles);
+        self
+    }
 
-        let k_consumer: BaseConsumer = config.create().expect("Failed to create Kakfa consumer");
-        RtTimestampConsumer {
-            consumer: k_consumer,
-            topic,
-            last_offset,
-        }
+    /// Sets the number of raw consistency records retained for each BYO source.
+    pub fn byo_record_history(mut self, byo_record_history: usize) -> Self {
+        self.config.byo_record_history = byo_record_history;
+        self
     }
 
-    /// Creates a RT Kafka connector
-    fn create_byo_connector(
-        &self,
-        url: Url,
-        topic: String,
-        id: SourceInstanceId,
-        ssl_certificate_file: Option<PathBuf>,
-        timestamp_topic: String,
-    ) -> ByoTimestampConsumer {
-        let mut config = ClientConfig::new();
-        config
-            .set("auto.offset.reset", "smallest")
-            .set(
-                "group.id",
-                &format!("materialize-byo-{}-{}", &timestamp_topic, id),
-            )
-            .set("enable.auto.commit", "false")
-            .set("enable.partition.eof", "false")
-            .set("auto.offset.reset", "earliest")
-            .set("session.timeout.ms", "300000")
-            .set("max.poll.interval.ms", "300000") // 5 minutes
-            .set("fetch.message.max.bytes", "134217728")
-            .set("enable.sparse.connections", "true")
-            .set("bootstrap.servers", &url.to_string());
-
-        if let Some(path) = ssl_certificate_file {
-            config.set("security.protocol", "ssl");
-            config.set(
-                "ssl.ca.location",
-                path.to_str()
-                    .expect("Converting ssl certificate file path failed"),
-            );
-        }
+    /// Sets the time after a source is added during which it is not reported as stalled.
+    pub fn startup_grace_period(mut self, startup_grace_period: Duration) -> Self {
+        self.config.startup_grace_period = startup_grace_period;
+        self
+    }
 
-        let k_consumer: BaseConsumer = config.create().expect("Failed to create Kakfa consumer");
-        let consumer = ByoTimestampConsumer {
-            consumer: k_consumer,
-            topic,
-            timestamp_topic,
-        };
-        consumer
-            .consumer
-            .subscribe(&[&consumer.timestamp_topic])
-            .unwrap();
-        consumer
+    /// Sets the minimum time between two writes of assigned timestamps to storage.
+    pub fn persist_interval(mut self, persist_interval: Duration) -> Self {
+        self.config.persist_interval = Some(persist_interval);
+        self
     }
 
-    /// Recovers any existing timestamp updates for that (SourceId,ViewId) pair from the underlying
-    /// SQL database. Notifies the coordinator of these updates
-    fn rt_recover_source(&mut self, id: SourceInstanceId) -> i64 {
-        let ts_updates: Vec<_> = self
-            .storage()
-            .prepare("SELECT timestamp, offset FROM timestamps WHERE sid = ? AND vid = ? ORDER BY timestamp")
-            .expect("Failed to execute select statement")
-            .query_and_then(params![SqlVal(&id.sid), SqlVal(&id.vid)], |row| -> Result<_, failure::Error> {
-                let timestamp: SqlVal<u64> = row.get(0)?;
-                let offset: SqlVal<i64> = row.get(1)?;
-                Ok((timestamp.0, offset.0))
-            })
-            .expect("Failed to parse SQL result")
-            .collect();
+    /// Sets the number of timestamping passes between two checkpoints.
+    pub fn checkpoint_interval(mut self, checkpoint_interval: u32) -> Self {
+        self.config.checkpoint_interval = Some(checkpoint_interval);
+        self
+    }
 
-        let mut max_offset = 0;
-        for row in ts_updates {
-            let (ts, offset) = row.expect("Failed to parse SQL result");
-            max_offset = if offset > max_offset {
-                offset
-            } else {
-                max_offset
-            };
-            self.coord_channel
-                .sender
-                .send(TimestampMessage::Update(id, ts, offset))
-                .expect("Failed to send timestamp update to coordinator");
-        }
-        max_offset
+    /// Sets whether recovery only sends the latest persisted timestamp of each source.
+    pub fn fast_recovery(mut self, fast_recovery: bool) -> Self {
+        self.config.fast_recovery = fast_recovery;
+        self
     }
 
-    /// Query real-time sources for the current max offset that has been generated for that source
-    /// Set the new timestamped offset to min(max_offset, last_offset + increment_size): this ensures
-    /// that we never create an overly large batch of messages for the same timestamp (which would
-    /// prevent views from becoming visible in a timely fashion)
-    fn rt_query_sources(&mut self) -> Vec<(SourceInstanceId, i64)> {
-        let mut result = vec![];
-        for (id, cons) in self.rt_sources.iter_mut() {
-            let watermark = cons
-                .consumer
-                .fetch_watermarks(&cons.topic, 0, Duration::from_secs(1));
-            match watermark {
-                Ok(watermark) => {
-                    let high = watermark.1 - 1;
-                    // Bound the next timestamp to be no more than max_increment_size in the future
-                    let next_ts = if (high - cons.last_offset) > self.max_increment_size {
-                        cons.last_offset + self.max_increment_size
-                    } else {
-                        high
-                    };
-                    cons.last_offset = next_ts;
-                    result.push((*id, next_ts))
-                }
-                Err(e) => {
-                    error!("Failed to obtain Kafka Watermark Information: {} {}", id, e);
-                }
-            }
-        }
-        result
+    /// Sets the largest real-time timestamp that is assigned.
+    pub fn timestamp_ceiling(mut self, timestamp_ceiling: u64) -> Self {
+        self.config.timestamp_ceiling = timestamp_ceiling;
+        self
     }
 
-    /// Persist timestamp updates to the underlying storage when using the
-    /// real-time timestamping logic.
-    fn rt_persist_timestamp(&self, ts_updates: &[(SourceInstanceId, i64)]) {
-        let storage = self.storage();
-        for (id, offset) in ts_updates {
-            let mut stmt = storage
-                .prepare_cached(
-                    "INSERT INTO timestamps (sid, vid, timestamp, offset) VALUES (?, ?, ?, ?)",
-                )
-                .expect(
-                    "Failed to prepare insert statement into persistent store. \
-                     Hint: increase the system file descriptor limit.",
-                );
-            while let Err(e) = stmt.execute(params![
-                SqlVal(&id.sid),
-                SqlVal(&id.vid),
-                SqlVal(&self.current_timestamp),
-                SqlVal(&offset)
-            ]) {
-                error!(
-                    "Failed to insert statement into persistent store: {}. \
-                     Hint: increase the system file descriptor limit.",
-                    e
-                );
-                std::thread::sleep(Duration::from_secs(1));
-            }
-        }
+    /// Sets whether the timestamper starts as a standby.
+    pub fn standby(mut self, standby: bool) -> Self {
+        self.config.standby = standby;
+        self
     }
 
-    /// Notify coordinator of a batch of timestamp updates, all with the same timestamp
-    /// Used in real-time timestamping logic, where a set of sources get assigned the same
-    /// timestamp
-    fn rt_notify_coordinator(&self, ts_updates: Vec<(SourceInstanceId, i64)>) {
-        self.coord_channel
-            .sender
-            .send(TimestampMessage::BatchedUpdate(
-                self.current_timestamp,
-                ts_updates,
-            ))
-            .expect("Failed to send timestamp update to coordinator");
+    /// Builds the `TimestampConfig`.
+    pub fn build(self) -> TimestampConfig {
+        self.config
     }
+}
 
-    /// Generates a timestamp that is guaranteed to be monotonically increasing.
-    /// This may require multiple calls to the underlying now() system method, which is not
-    /// guaranteed to increase monotonically
-    fn rt_generate_next_timestamp(&mut self) {
-        let mut new_ts = 0;
-        while new_ts <= self.current_timestamp {
-            let start = SystemTime::now();
-            new_ts = start
-                .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards")
-                .as_millis() as u64;
-        }
-        assert!(new_ts > self.current_timestamp);
-        self.current_timestamp = new_ts;
+/// Key timestamping events, logged as JSON lines when structured logging is enabled
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum TimestampEvent<'a> {
+    SourceAdded {
+        source_id: String,
+        topic: &'a str,
+        consistency_topic: Option<&'a str>,
+    },
+    SourceDropped {
+        source_id: String,
+    },
+    TimestampAdvanced {
+        source_id: String,
+        timestamp: u64,
+        offset: i64,
+    },
+    RecordsRejected {
+        source_id: String,
+        count: u64,
+        error: &'a str,
+    },
+    RecoveryCompleted {
+        source_id: String,
+        updates: usize,
+        max_offset: i64,
+    },
+    RetentionSkipped {
+        source_id: String,
+        from_offset: i64,
+        to_offset: i64,
+    },
+    SourceLive {
+        source_id: String,
+        offset: i64,
+    },
+    TopicRecreated {
+        source_id: String,
+        topic: &'a str,
+        last_offset: i64,
+        low_watermark: i64,
+    },
+    ProducerStalled {
+        source_id: String,
+        consistency_topic: &'a str,
+        idle_secs: u64,
+    },
+}
+
+/// Logs a timestamping event: as a JSON line when structured logging is enabled, and as
+/// `message` at `level` otherwise
+fn log_event(
+    structured_logging: bool,
+    level: Level,
+    event: &TimestampEvent,
+    message: fmt::Arguments,
+) {
+    if structured_logging {
+        info!(
+            "{}",
+            serde_json::to_string(event).expect("timestamp event serialization cannot fail")
+        );
+    } else {
+        log!(level, "{}", message);
+    }
+}
+
+#[derive(Debug)]
+pub enum TimestampMessage {
+    Add(SourceInstanceId, KafkaSourceConnector, Consistency),
+    DropInstance(SourceInstanceId),
+    BatchedUpdate(u64, Vec<(SourceInstanceId, i64)>),
+    Update(SourceInstanceId, u64, i64),
+    /// Sent when a real-time source reaches its Kafka high watermark (`true`), and when
+    /// the watermark moves ahead of it again (`false`)
+    CaughtUp(SourceInstanceId, bool),
+    /// Sent when a bounded source has been fully timestamped and is no longer tracked
+    Complete(SourceInstanceId),
+    /// Sent once per real-time source, when it is first timestamped beyond the offsets
+    /// recovered at startup
+    Live(SourceInstanceId),
+    /// Sent when a source cannot be timestamped, along with the reason
+    AddRejected(SourceInstanceId, String),
+    /// Requests an immediate timestamping pass, outside of the regular cadence
+    ForceAdvance,
+    /// Requests that the latest timestamp of every active source be sent again, so that a
+    /// reconnecting coordinator can rebuild its state
+    Resync,
+    /// Requests that a broken source be polled again on every pass, e.g. after the issue
+    /// that caused its failures has been fixed
+    RetrySource(SourceInstanceId),
+    /// Requests a timestamping pass when the timestamper runs in pull mode
+    Tick,
+    /// Stops advancing the timestamps of a source, e.g. during upstream maintenance. The
+    /// source keeps all of its state
+    Pause(SourceInstanceId),
+    /// Resumes timestamping of a paused source
+    Resume(SourceInstanceId),
+    /// Turns a standby timestamper into the active one. It resumes from the timestamps
+    /// persisted by the previously active timestamper
+    Promote,
+    /// Rewinds a real-time source so that the messages that were assigned the given
+    /// timestamp or a later one are timestamped again. `current_timestamp` never moves
+    /// back, so these messages are assigned new timestamps ahead of every timestamp
+    /// already sent, rather than their original ones. Dataflows that already consumed
+    /// them, or compacted past the rewound timestamp, do not see them again unless the
+    /// source is also read again from the rewound offset
+    Rewind(SourceInstanceId, u64),
+    Shutdown,
+}
+
+/// The outcome of validating the records of a consistency topic
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    /// Number of records that parsed successfully
+    pub parsed: usize,
+    /// Number of records that failed to parse
+    pub rejected: usize,
+    /// Distinct source names observed in the parsed records
+    pub source_names: HashSet<String>,
+}
+
+/// How far a real-time source has been timestamped, relative to its topic
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RtSourceProgress {
+    pub source_id: SourceInstanceId,
+    /// Last offset that was timestamped
+    pub last_offset: i64,
+    /// Last offset of the topic as of the latest watermark query, if any
+    pub high_watermark: Option<i64>,
+    /// Maximum number of offsets timestamped in a single pass
+    pub max_increment_size: i64,
+}
+
+/// A persisted timestamp assignment of a source: messages up to and including `offset`
+/// were assigned `timestamp`
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub struct TimestampRow {
+    pub timestamp: u64,
+    pub offset: i64,
+}
+
+/// A persisted timestamp of a source, in the portable format of
+/// `Timestamper::export_timestamps`
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ExportedTimestamp {
+    pub source_id: SourceInstanceId,
+    pub timestamp: u64,
+    pub offset: i64,
+    /// Whether the row belongs to the checkpoint of the source
+    pub checkpoint: bool,
+}
+
+pub struct TimestampChannel {
+    pub sender: std::sync::mpsc::Sender<TimestampMessage>,
+    pub receiver: std::sync::mpsc::Receiver<TimestampMessage>,
+}
+
+/// A timestamp assigned to the messages of a source up to and including `offset`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SourceTimestampUpdate {
+    pub source_id: SourceInstanceId,
+    pub timestamp: u64,
+    pub offset: i64,
+}
+
+/// The destination of the timestamps assigned by a `Timestamper`. By default, they are sent
+/// to the coordinator over the `TimestampChannel`
+pub trait TimestampSink: Send {
+    fn advance(&self, update: SourceTimestampUpdate);
+
+    /// Advances several sources to the same timestamp at once
+    fn advance_batch(&self, timestamp: u64, updates: Vec<(SourceInstanceId, i64)>) {
+        for (source_id, offset) in updates {
+            self.advance(SourceTimestampUpdate {
+                source_id,
+                timestamp,
+                offset,
+            });
+        }
+    }
+}
+
+/// Notified of the lifecycle events of the sources of a `Timestamper`. Observers are
+/// called on the timestamping thread, so they must return quickly, e.g. by forwarding
+/// the event to a channel, rather than block the update loop
+pub trait TimestamperObserver: Send {
+    /// Called once a source is being timestamped
+    fn on_source_added(&self, _id: SourceInstanceId) {}
+
+    /// Called once a source is no longer timestamped
+    fn on_source_dropped(&self, _id: SourceInstanceId) {}
+
+    /// Called once the persisted timestamps of a source have been recovered, with the
+    /// largest recovered offset, or -1 if there was none
+    fn on_recovery_complete(&self, _id: SourceInstanceId, _max_offset: i64) {}
+
+    /// Called when a source has failed too many times in a row, and is only polled every
+    /// `BROKEN_SOURCE_RETRY_INTERVAL`
+    fn on_source_broken(&self, _id: SourceInstanceId, _reason: &str) {}
+
+    /// Called when a broken source is polled on every pass again
+    fn on_source_healthy(&self, _id: SourceInstanceId) {}
+}
+
+impl TimestampSink for std::sync::mpsc::Sender<TimestampMessage> {
+    fn advance(&self, update: SourceTimestampUpdate) {
+        self.send(TimestampMessage::Update(
+            update.source_id,
+            update.timestamp,
+            update.offset,
+        ))
+        .expect("Failed to send timestamp update to coordinator");
+    }
+
+    fn advance_batch(&self, timestamp: u64, updates: Vec<(SourceInstanceId, i64)>) {
+        self.send(TimestampMessage::BatchedUpdate(timestamp, updates))
+            .expect("Failed to send timestamp update to coordinator");
+    }
+}
+
+impl TimestampSink for futures::channel::mpsc::UnboundedSender<SourceTimestampUpdate> {
+    fn advance(&self, update: SourceTimestampUpdate) {
+        self.unbounded_send(update)
+            .expect("Failed to send timestamp update to stream");
+    }
+}
+
+/// Timestamp consumer: wrapper around Kafka consumer that stores necessary information
+/// about topics and offset for real-time consistency
+struct RtTimestampConsumer {
+    consumer: BaseConsumer,
+    connector: KafkaSourceConnector,
+    group_id: String,
+    topic: String,
+    last_offset: i64,
+    // Source-specific max increment size, if any
+    max_increment_size: Option<i64>,
+    // Whether last_offset has reached the high watermark
+    caught_up: bool,
+    // Whether the topic is bounded
+    bounded: bool,
+    // Offset of the last message of a bounded topic, once known. Unless configured, it is
+    // the last offset of the topic as of the first watermark query that finds a message
+    end_offset: Option<i64>,
+    // Whether assigned timestamps are persisted
+    durable: bool,
+    // Last timestamp and offset sent to the coordinator, if any
+    last_update: Option<(u64, i64)>,
+    // Whether timestamps are taken from the Kafka timestamps of messages
+    event_time: bool,
+    // Consumer that reads the Kafka timestamps of messages of an event time source. The
+    // consumer above only queries watermarks
+    event_consumer: Option<BaseConsumer>,
+    // Largest offset recovered from the persisted history, until the source is
+    // timestamped beyond it
+    recovered_offset: Option<i64>,
+    // Number of consecutive failures to fetch watermarks
+    consecutive_failures: u32,
+    // Rack of the consumer, used to fetch from the closest replica
+    client_rack: Option<String>,
+    // Whether timestamping of the source is paused
+    paused: bool,
+    // Whether the topic was found to be recreated since its persisted history was last
+    // cleared
+    recreated: bool,
+    // Last offset of the topic as of the latest watermark query, if any
+    high_watermark: Option<i64>,
+    // Wall-clock time at which the consumer was created
+    created_at: SystemTime,
+}
+
+impl RtTimestampConsumer {
+    /// Records the end of a bounded topic from the last offset of its first high
+    /// watermark that covers a message. An empty topic has no end yet, as it may not have
+    /// been written to yet
+    fn observe_high_watermark(&mut self, high: i64) {
+        if self.bounded && self.end_offset.is_none() && high >= 0 {
+            self.end_offset = Some(high);
+        }
+    }
+
+    /// Whether a bounded topic has been timestamped up to its end. Having caught up with
+    /// the high watermark is not enough, as the topic may only be idle
+    fn is_complete(&self) -> bool {
+        self.bounded
+            && self
+                .end_offset
+                .map_or(false, |end_offset| self.last_offset >= end_offset)
+    }
+
+    /// Returns the Kafka timestamp, in milliseconds, of the message at `offset`
+    fn event_time_at(&mut self, offset: i64, timeout: Duration) -> Option<u64> {
+        let consumer = self.event_consumer.as_ref()?;
+        let mut partitions = TopicPartitionList::new();
+        partitions.add_partition_offset(&self.topic, 0, Offset::Offset(offset));
+        if let Err(e) = consumer.assign(&partitions) {
+            error!(
+                "Failed to seek to offset {} of {}: {}",
+                offset, self.topic, e
+            );
+            return None;
+        }
+        match consumer.poll(timeout) {
+            Some(Ok(message)) => match message.timestamp().to_millis() {
+                Some(ts) if ts >= 0 => Some(ts as u64),
+                _ => {
+                    error!(
+                        "Message at offset {} of {} has no timestamp",
+                        message.offset(),
+                        self.topic
+                    );
+                    None
+                }
+            },
+            Some(Err(e)) => {
+                error!(
+                    "Failed to fetch message at offset {} of {}: {}",
+                    offset, self.topic, e
+                );
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Queries the high watermark of the topic, and advances `last_offset` towards it by at
+    /// most `max_increment_size`. Messages that were deleted by log retention before being
+    /// timestamped are skipped, and a topic that was deleted and recreated is timestamped
+    /// from its new low watermark. Returns the new offset, along with the new caught up
+    /// status if it changed
+    fn query(
+        &mut self,
+        id: SourceInstanceId,
+        max_increment_size: i64,
+        watermark_timeout: Duration,
+        structured_logging: bool,
+    ) -> Result<(i64, Option<bool>), KafkaError> {
+        match self
+            .consumer
+            .fetch_watermarks(&self.topic, 0, watermark_timeout)
+        {
+            Ok(watermark) => {
+                self.consecutive_failures = 0;
+                let (low, high) = (watermark.0, watermark.1 - 1);
+                self.high_watermark = Some(high);
+                self.observe_high_watermark(high);
+                if high < self.last_offset {
+                    // Offsets restart from zero when a topic is deleted and recreated
+                    log_event(
+                        structured_logging,
+                        Level::Warn,
+                        &TimestampEvent::TopicRecreated {
+                            source_id: id.to_string(),
+                            topic: &self.topic,
+                            last_offset: self.last_offset,
+                            low_watermark: low,
+                        },
+                        format_args!(
+                            "High watermark of {} moved back from {} to {}. Assuming the topic was recreated, and timestamping it from offset {}",
+                            self.topic, self.last_offset, high, low
+                        ),
+                    );
+                    self.last_offset = low - 1;
+                    self.last_update = self.last_update.map(|(ts, _)| (ts, low - 1));
+                    self.recovered_offset = self.recovered_offset.map(|_| low - 1);
+                    self.recreated = true;
+                }
+                if self.last_offset < low - 1 {
+                    log_event(
+                        structured_logging,
+                        Level::Warn,
+                        &TimestampEvent::RetentionSkipped {
+                            source_id: id.to_string(),
+                            from_offset: self.last_offset + 1,
+                            to_offset: low - 1,
+                        },
+                        format_args!(
+                            "Offsets {} to {} of {} were deleted by log retention before being timestamped. Skipping them",
+                            self.last_offset + 1,
+                            low - 1,
+                            self.topic
+                        ),
+                    );
+                    self.last_offset = low - 1;
+                }
+                let max_increment_size = self.max_increment_size.unwrap_or(max_increment_size);
+                let next_ts = rt_next_offset(self.last_offset, high, max_increment_size);
+                self.last_offset = next_ts;
+                let caught_up = next_ts >= high;
+                if caught_up != self.caught_up {
+                    self.caught_up = caught_up;
+                    Ok((next_ts, Some(caught_up)))
+                } else {
+                    Ok((next_ts, None))
+                }
+            }
+            Err(e) => {
+                error!("Failed to obtain Kafka Watermark Information: {} {}", id, e);
+                self.consecutive_failures += 1;
+                if self.consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
+                    self.reconnect(id);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Replaces the Kafka consumer with a new one. The last timestamped offset is preserved
+    fn reconnect(&mut self, id: SourceInstanceId) {
+        warn!(
+            "Recreating Kafka consumer for Source {} after {} consecutive failures",
+            id, self.consecutive_failures
+        );
+        self.consecutive_failures = 0;
+        match create_rt_kafka_consumer(&self.group_id, &self.connector, self.client_rack.as_deref())
+        {
+            Ok(consumer) => self.consumer = consumer,
+            Err(e) => error!("Failed to recreate Kafka consumer for Source {}: {}", id, e),
+        }
+        if self.event_consumer.is_some() {
+            match create_event_time_kafka_consumer(
+                &self.group_id,
+                &self.connector,
+                self.client_rack.as_deref(),
+            ) {
+                Ok(consumer) => self.event_consumer = Some(consumer),
+                Err(e) => error!("Failed to recreate Kafka consumer for Source {}: {}", id, e),
+            }
+        }
+    }
+}
+
+/// What is needed to create the Kafka consumer of a paused source again, after it was
+/// closed to release file descriptors
+enum ReleasedSource {
+    Rt {
+        connector: KafkaSourceConnector,
+        last_offset: i64,
+        last_update: Option<(u64, i64)>,
+    },
+    Byo {
+        connector: KafkaSourceConnector,
+        timestamp_topic: String,
+        consistency_offset: Option<i64>,
+        transaction_events: i64,
+        last_offset: i64,
+        last_update: Option<(u64, i64)>,
+    },
+}
+
+/// A message read from a consistency topic
+struct ConsistencyMessage {
+    key: Option<Vec<u8>>,
+    payload: Vec<u8>,
+    offset: i64,
+    // Kafka timestamp of the message, in milliseconds, if any
+    timestamp: Option<u64>,
+    // Value of the connector's format header, if the message carries it
+    format: Option<Vec<u8>>,
+}
+
+struct ByoTimestampConsumer {
+    consumer: BaseConsumer,
+    connector: KafkaSourceConnector,
+    group_id: String,
+    topic: String,
+    timestamp_topic: String,
+    // Whether the consistency topic is log-compacted
+    compacted: bool,
+    // Rejected consistency records that have not yet been reported
+    rejections: RejectionLog,
+    // Offset of the last record read from the consistency topic
+    consistency_offset: Option<i64>,
+    // Source-specific max increment size, if any
+    max_increment_size: Option<i64>,
+    // Largest offset of the source that was timestamped, or -1 if there is none
+    last_offset: i64,
+    // Whether the topic is bounded
+    bounded: bool,
+    // Offset of the last message of a bounded topic, once known. Unless configured, it is
+    // the last offset of the topic as of the first watermark query that finds a message
+    end_offset: Option<i64>,
+    // Consumer that queries the watermarks of a bounded topic, until its end is known. The
+    // consistency topic may be served by other brokers than the topic itself
+    end_consumer: Option<BaseConsumer>,
+    // Whether assigned timestamps are persisted
+    durable: bool,
+    // Last timestamp and offset sent to the coordinator, if any
+    last_update: Option<(u64, i64)>,
+    // Number of consecutive failures to poll the consistency topic
+    consecutive_failures: u32,
+    // Rack of the consumer, used to fetch from the closest replica
+    client_rack: Option<String>,
+    // Whether timestamping of the source is paused
+    paused: bool,
+    // Time at which the last record was read from the consistency topic, or at which
+    // reading started
+    last_record: Instant,
+    // Whether the consistency producer has been reported as stalled
+    stalled: bool,
+    // Number of consecutive polls that returned no record
+    empty_cycles: u32,
+    // Raw payloads of the latest consistency records, oldest first
+    recent_records: VecDeque<Vec<u8>>,
+    // Maximum number of payloads kept in recent_records
+    record_history: usize,
+    // Time at which the source was added
+    added: Instant,
+    // Wall-clock time at which the consumer was created
+    created_at: SystemTime,
+    // Number of events of the source counted in Debezium transaction records
+    transaction_events: i64,
+}
+
+impl ByoTimestampConsumer {
+    /// Queries the high watermark of a bounded topic whose end is not known yet, and
+    /// records the end from the first one that covers a message
+    fn query_end_offset(&mut self, watermark_timeout: Duration) {
+        let result = match &self.end_consumer {
+            Some(consumer) => consumer.fetch_watermarks(&self.topic, 0, watermark_timeout),
+            None => return,
+        };
+        match result {
+            Ok((_low, high)) if high > 0 => {
+                self.end_offset = Some(high - 1);
+                self.end_consumer = None;
+            }
+            Ok(_) => (),
+            Err(e) => error!(
+                "Failed to obtain Kafka Watermark Information: {} {}",
+                self.topic, e
+            ),
+        }
+    }
+
+    /// Whether a bounded topic has been timestamped up to its end. Reaching the end of the
+    /// consistency topic is not enough, as its producer may only be idle
+    fn is_complete(&self) -> bool {
+        self.bounded
+            && self
+                .end_offset
+                .map_or(false, |end_offset| self.last_offset >= end_offset)
+    }
+
+    /// Reports the consistency producer as stalled once no record has been read for
+    /// `threshold`, and as resumed once records flow again. Sources are not reported
+    /// within `grace_period` of being added
+    fn check_stalled(
+        &mut self,
+        id: SourceInstanceId,
+        received: bool,
+        threshold: Duration,
+        grace_period: Duration,
+        structured_logging: bool,
+    ) {
+        if received {
+            self.last_record = Instant::now();
+            if self.stalled {
+                self.stalled = false;
+                STALLED_PRODUCERS
+                    .with_label_values(&[&id.to_string()])
+                    .set(0);
+                info!(
+                    "Consistency producer of Source {} resumed on topic {}",
+                    id, self.timestamp_topic
+                );
+            }
+            return;
+        }
+        let idle = self.last_record.elapsed();
+        if self.stalled || idle < threshold || self.added.elapsed() < grace_period {
+            return;
+        }
+        self.stalled = true;
+        STALLED_PRODUCERS
+            .with_label_values(&[&id.to_string()])
+            .set(1);
+        log_event(
+            structured_logging,
+            Level::Warn,
+            &TimestampEvent::ProducerStalled {
+                source_id: id.to_string(),
+                consistency_topic: &self.timestamp_topic,
+                idle_secs: idle.as_secs(),
+            },
+            format_args!(
+                "Consistency producer appears stalled: no record on topic {} for Source {} in {} seconds",
+                self.timestamp_topic,
+                id,
+                idle.as_secs()
+            ),
+        );
+    }
+
+    /// Reports the source as idle once `threshold` consecutive polls returned no record,
+    /// and as resumed once records flow again. Each is only reported once
+    fn check_idle(&mut self, id: SourceInstanceId, received: bool, threshold: Option<u32>) {
+        let threshold = match threshold {
+            Some(threshold) => threshold,
+            None => return,
+        };
+        if received {
+            if self.empty_cycles >= threshold {
+                info!(
+                    "Source {} is no longer idle: records resumed on topic {}",
+                    id, self.timestamp_topic
+                );
+            }
+            self.empty_cycles = 0;
+            return;
+        }
+        self.empty_cycles = self.empty_cycles.saturating_add(1);
+        if self.empty_cycles == threshold {
+            info!(
+                "Source {} has been idle for {} cycles: no record on topic {}",
+                id, threshold, self.timestamp_topic
+            );
+        }
+    }
+
+    /// Replaces the Kafka consumer with a new one, which resumes reading the consistency
+    /// topic after the last record that was read
+    fn reconnect(&mut self, id: SourceInstanceId) {
+        warn!(
+            "Recreating Kafka consumer for Source {} after {} consecutive failures",
+            id, self.consecutive_failures
+        );
+        self.consecutive_failures = 0;
+        self.recreate_consumer(id);
+    }
+
+    /// Creates a new Kafka consumer that starts reading the consistency topic after
+    /// `consistency_offset`
+    fn recreate_consumer(&mut self, id: SourceInstanceId) {
+        match create_byo_kafka_consumer(
+            &self.group_id,
+            &self.connector,
+            self.client_rack.as_deref(),
+            &self.timestamp_topic,
+            self.consistency_offset,
+        ) {
+            Ok(consumer) => self.consumer = consumer,
+            Err(e) => error!("Failed to recreate Kafka consumer for Source {}: {}", id, e),
+        }
+    }
+}
+
+/// Accumulates rejected consistency records so that a misbehaving producer results in a
+/// periodic summary rather than one log line per record
+#[derive(Default)]
+struct RejectionLog {
+    // Number of records rejected since the last report
+    count: u64,
+    // Reason the most recent record was rejected
+    last_error: Option<String>,
+    // Time of the last report
+    last_report: Option<Instant>,
+}
+
+impl RejectionLog {
+    fn record(&mut self, topic: &str, err: failure::Error) {
+        if self.count == 0 {
+            debug!("Rejected consistency record for source {}: {}", topic, err);
+        }
+        self.count += 1;
+        self.last_error = Some(err.to_string());
+    }
+
+    /// Returns the number of rejected records and the most recent error, at most once
+    /// every `REJECTION_LOG_INTERVAL`
+    fn report(&mut self) -> Option<(u64, String)> {
+        if self.count == 0 {
+            return None;
+        }
+        if let Some(last_report) = self.last_report {
+            if last_report.elapsed() < REJECTION_LOG_INTERVAL {
+                return None;
+            }
+        }
+        let count = self.count;
+        self.count = 0;
+        self.last_report = Some(Instant::now());
+        Some((count, self.last_error.take().unwrap_or_default()))
+    }
+}
+
+/// Reads the next batch of consistency records. Stops once the message cap is reached or
+/// `deadline` has passed, whichever comes first
+fn byo_query_source(
+    consumer: &mut ByoTimestampConsumer,
+    max_increment_size: i64,
+    deadline: Instant,
+    watermark_timeout: Duration,
+) -> Vec<ConsistencyMessage> {
+    // Compacted consistency topics are read up to their high watermark, as otherwise the
+    // latest record for a key might never be applied
+    let high_watermark = if consumer.compacted {
+        match consumer
+            .consumer
+            .fetch_watermarks(&consumer.timestamp_topic, 0, watermark_timeout)
+        {
+            Ok((_low, high)) => Some(high),
+            Err(e) => {
+                error!(
+                    "Failed to obtain Kafka Watermark Information: {} {}",
+                    consumer.timestamp_topic, e
+                );
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let max_increment_size = consumer.max_increment_size.unwrap_or(max_increment_size);
+    let mut messages = vec![];
+    loop {
+        let batch_size = if consumer.compacted {
+            max_increment_size
+        } else {
+            max_increment_size - messages.len() as i64
+        };
+        let batch = get_next_messages(consumer, batch_size.max(1) as usize);
+        let offset = match batch.last() {
+            Some(message) => message.offset,
+            None => break,
+        };
+        messages.extend(batch);
+        consumer.consistency_offset = Some(offset);
+        match high_watermark {
+            Some(high) => {
+                if offset >= high - 1 {
+                    break;
+                }
+            }
+            None => {
+                if !consumer.compacted && messages.len() as i64 >= max_increment_size {
+                    // Make sure to bound the number of timestamp updates we have at once,
+                    // to avoid overflowing the system
+                    break;
+                }
+            }
+        }
+        if Instant::now() >= deadline {
+            // Keep the update loop responsive when the consistency topic trickles in
+            break;
+        }
+    }
+    messages
+}
+
+/// Parses a consistency record of the form SourceName,TS,Offset
+pub(crate) fn byo_parse_ts_update(payload: &[u8]) -> Result<(String, u64, i64), failure::Error> {
+    byo_parse_csv_ts_update(payload, &CsvConsistencyColumns::default())
+}
+
+/// Parses a consistency record whose values are found in the given `columns`. The record
+/// is read as CSV, so the source name may be quoted, and surrounding whitespace
+/// (including a trailing newline) is ignored
+fn byo_parse_csv_ts_update(
+    payload: &[u8],
+    columns: &CsvConsistencyColumns,
+) -> Result<(String, u64, i64), failure::Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .from_reader(payload);
+    let mut records = reader.records();
+    let record = match records.next() {
+        Some(Ok(record)) => record,
+        Some(Err(err)) => bail!("incorrect payload format: {}", err),
+        None => bail!("incorrect payload format: empty record"),
+    };
+    if records.next().is_some() {
+        bail!("incorrect payload format: expected a single record");
+    }
+    let width = 1 + columns
+        .source_name
+        .max(columns.timestamp)
+        .max(columns.offset);
+    if record.len() != width {
+        bail!(
+            "incorrect payload format. Expected {} columns, found {}",
+            width,
+            record.len()
+        );
+    }
+    let topic_name = String::from(&record[columns.source_name]);
+    let ts = match record[columns.timestamp].parse::<u64>() {
+        Ok(i) => i,
+        Err(err) => bail!("incorrect timestamp format {}", err),
+    };
+    let offset = match record[columns.offset].parse::<i64>() {
+        Ok(i) => i,
+        Err(err) => bail!("incorrect offset format {}", err),
+    };
+    Ok((topic_name, ts, offset))
+}
+
+/// Parses a consistency record made of a JSON object, whose values are located by the
+/// JSON pointers in `fields`
+fn byo_parse_json_ts_update(
+    payload: &[u8],
+    fields: &JsonConsistencyFields,
+) -> Result<(String, u64, i64), failure::Error> {
+    let record: serde_json::Value = match serde_json::from_slice(payload) {
+        Ok(record) => record,
+        Err(err) => bail!("incorrect payload format: {}", err),
+    };
+    let field = |pointer: &str| match record.pointer(pointer) {
+        Some(value) => Ok(value),
+        None => Err(format_err!("missing required field {}", pointer)),
+    };
+    let topic_name = match field(&fields.source_name)?.as_str() {
+        Some(name) => name.to_owned(),
+        None => bail!("field {} must be a string", fields.source_name),
+    };
+    let ts = match field(&fields.timestamp)?.as_u64() {
+        Some(ts) => ts,
+        None => bail!("field {} must be an unsigned integer", fields.timestamp),
+    };
+    let offset = match field(&fields.offset)?.as_i64() {
+        Some(offset) => offset,
+        None => bail!("field {} must be an integer", fields.offset),
+    };
+    Ok((topic_name, ts, offset))
+}
+
+/// Parses a consistency record in the given format
+fn byo_decode_ts_update(
+    payload: &[u8],
+    format: &ConsistencyFormat,
+) -> Result<(String, u64, i64), failure::Error> {
+    match format {
+        ConsistencyFormat::Csv(columns) => byo_parse_csv_ts_update(payload, columns),
+        ConsistencyFormat::Json(fields) => byo_parse_json_ts_update(payload, fields),
+        ConsistencyFormat::DebeziumTransaction => {
+            bail!("Debezium transaction records do not carry offsets")
+        }
+    }
+}
+
+/// A completed transaction, as described by the `END` record of a Debezium transaction
+/// metadata topic
+#[derive(Debug, PartialEq)]
+struct DebeziumTransaction {
+    // Time of the transaction in milliseconds, if recorded
+    ts_ms: Option<u64>,
+    // Number of events of the transaction in each data collection
+    data_collections: Vec<(String, i64)>,
+}
+
+/// Parses a record of a Debezium transaction metadata topic. `BEGIN` records carry no
+/// event counts, so only `END` records yield a transaction
+fn byo_parse_debezium_transaction(
+    payload: &[u8],
+) -> Result<Option<DebeziumTransaction>, failure::Error> {
+    let record: serde_json::Value = match serde_json::from_slice(payload) {
+        Ok(record) => record,
+        Err(err) => bail!("incorrect payload format: {}", err),
+    };
+    // Records written with schemas enabled wrap the value in a payload field
+    let record = record.get("payload").unwrap_or(&record);
+    match record.get("status").and_then(|status| status.as_str()) {
+        Some("BEGIN") => return Ok(None),
+        Some("END") => (),
+        Some(status) => bail!("unknown transaction status {}", status),
+        None => bail!("missing required field status"),
+    }
+    let ts_ms = match record.get("ts_ms") {
+        None | Some(serde_json::Value::Null) => None,
+        Some(ts_ms) => match ts_ms.as_u64() {
+            Some(ts_ms) => Some(ts_ms),
+            None => bail!("field ts_ms must be an unsigned integer"),
+        },
+    };
+    let collections = match record.get("data_collections").and_then(|c| c.as_array()) {
+        Some(collections) => collections,
+        None => bail!("field data_collections must be an array"),
+    };
+    let mut data_collections = vec![];
+    for collection in collections {
+        let name = match collection.get("data_collection").and_then(|n| n.as_str()) {
+            Some(name) => name.to_owned(),
+            None => bail!("field data_collection must be a string"),
+        };
+        let event_count = match collection.get("event_count").and_then(|c| c.as_i64()) {
+            Some(count) if count >= 0 => count,
+            _ => bail!("field event_count must be a non-negative integer"),
+        };
+        data_collections.push((name, event_count));
+    }
+    Ok(Some(DebeziumTransaction {
+        ts_ms,
+        data_collections,
+    }))
+}
+
+/// Returns whether the Debezium data collection `collection`, e.g. `inventory.customers`,
+/// is the one written to `topic`, e.g. `dbserver1.inventory.customers`
+fn debezium_collection_matches(topic: &str, collection: &str) -> bool {
+    topic == collection
+        || (topic.ends_with(collection) && topic[..topic.len() - collection.len()].ends_with('.'))
+}
+
+/// Appends the raw payloads of `messages` to `records`, dropping the oldest ones so that
+/// at most `capacity` are kept
+fn retain_recent_records(
+    records: &mut VecDeque<Vec<u8>>,
+    messages: &[ConsistencyMessage],
+    capacity: usize,
+) {
+    let skip = messages.len().saturating_sub(capacity);
+    for message in &messages[skip..] {
+        if records.len() == capacity {
+            records.pop_front();
+        }
+        records.push_back(message.payload.clone());
+    }
+}
+
+/// Decompresses the payload of a consistency record
+fn byo_decompress(
+    payload: &[u8],
+    compression: ConsistencyCompression,
+) -> Result<Cow<[u8]>, failure::Error> {
+    match compression {
+        ConsistencyCompression::None => Ok(Cow::Borrowed(payload)),
+        ConsistencyCompression::Gzip => {
+            let mut decompressed = vec![];
+            match libflate::gzip::Decoder::new(payload)
+                .and_then(|mut decoder| decoder.read_to_end(&mut decompressed))
+            {
+                Ok(_) => Ok(Cow::Owned(decompressed)),
+                Err(err) => bail!("invalid gzip payload: {}", err),
+            }
+        }
+        ConsistencyCompression::Snappy => match snap::Decoder::new().decompress_vec(payload) {
+            Ok(decompressed) => Ok(Cow::Owned(decompressed)),
+            Err(err) => bail!("invalid snappy payload: {}", err),
+        },
+    }
+}
+
+/// Parses the consistency record of a message. If the connector says so, the source name
+/// is taken from the message key rather than from the record itself
+fn byo_decode_message(
+    message: &ConsistencyMessage,
+    connector: &KafkaSourceConnector,
+    format: &ConsistencyFormat,
+) -> Result<(String, u64, i64), failure::Error> {
+    let payload = byo_decompress(&message.payload, connector.consistency_compression)?;
+    let (topic_name, ts, offset) = byo_decode_ts_update(&payload, format)?;
+    if !connector.consistency_source_name_from_key {
+        return Ok((topic_name, ts, offset));
+    }
+    match &message.key {
+        Some(key) => match str::from_utf8(key) {
+            Ok(key) => Ok((key.to_owned(), ts, offset)),
+            Err(err) => bail!("incorrect key format: {}", err),
+        },
+        None => bail!("missing message key"),
+    }
+}
+
+/// Adds the signed `correction`, in milliseconds, to a consistency timestamp. Fails if the
+/// corrected timestamp is negative or overflows
+fn correct_timestamp(ts: u64, correction: i64) -> Result<u64, failure::Error> {
+    let corrected = if correction >= 0 {
+        ts.checked_add(correction as u64)
+    } else {
+        ts.checked_sub(correction.wrapping_neg() as u64)
+    };
+    match corrected {
+        Some(ts) => Ok(ts),
+        None => bail!(
+            "timestamp {} is out of range once corrected by {} milliseconds",
+            ts,
+            correction
+        ),
+    }
+}
+
+/// Returns the format of a consistency record. If the connector names a format header
+/// and the record carries it, its value selects the format, so that sources of different
+/// formats can share a consistency topic. A CSV or JSON record selected that way is read
+/// with the columns or fields of the connector's format if it is of the same kind, and
+/// with the default ones otherwise. Other records are in the connector's format
+fn byo_record_format<'a>(
+    message: &ConsistencyMessage,
+    connector: &'a KafkaSourceConnector,
+) -> Result<Cow<'a, ConsistencyFormat>, failure::Error> {
+    let name = match &message.format {
+        Some(name) => match str::from_utf8(name) {
+            Ok(name) => name.trim().to_ascii_lowercase(),
+            Err(err) => bail!("incorrect format header: {}", err),
+        },
+        None => return Ok(Cow::Borrowed(&connector.consistency_format)),
+    };
+    let format = match (name.as_str(), &connector.consistency_format) {
+        ("csv", ConsistencyFormat::Csv(_))
+        | ("json", ConsistencyFormat::Json(_))
+        | ("debezium_transaction", ConsistencyFormat::DebeziumTransaction) => {
+            return Ok(Cow::Borrowed(&connector.consistency_format))
+        }
+        ("csv", _) => ConsistencyFormat::Csv(CsvConsistencyColumns::default()),
+        ("json", _) => ConsistencyFormat::Json(JsonConsistencyFields::default()),
+        ("debezium_transaction", _) => ConsistencyFormat::DebeziumTransaction,
+        (name, _) => bail!("unknown consistency format {}", name),
+    };
+    Ok(Cow::Owned(format))
+}
+
+/// Derives timestamp updates from consistency records, each decoded according to its
+/// format
+fn byo_extract_ts_update(
+    consumer: &mut ByoTimestampConsumer,
+    messages: Vec<ConsistencyMessage>,
+) -> Vec<(u64, i64)> {
+    let mut updates = vec![];
+    let mut last_ts = consumer.last_update.map(|(ts, _)| ts);
+    for message in messages {
+        let update = match byo_record_format(&message, &consumer.connector) {
+            Ok(format) => {
+                if *format == ConsistencyFormat::DebeziumTransaction {
+                    byo_debezium_ts_update(
+                        &message,
+                        &consumer.connector,
+                        &consumer.topic,
+                        last_ts,
+                        &mut consumer.transaction_events,
+                    )
+                } else {
+                    byo_ts_update(&message, &consumer.connector, &consumer.topic, &format)
+                }
+            }
+            Err(err) => Err(err),
+        };
+        match update {
+            Ok(Some((ts, offset))) => {
+                last_ts = Some(ts);
+                updates.push((ts, offset));
+            }
+            Ok(None) => (),
+            Err(err) => consumer.rejections.record(&consumer.topic, err),
+        }
+    }
+    let (updates, errors) = byo_check_offset_order(updates);
+    for err in errors {
+        consumer.rejections.record(&consumer.topic, err);
+    }
+    updates
+}
+
+/// Rejects the updates of a batch whose offset is behind that of an earlier update of the
+/// batch, as can happen when a CDC tool catches up. Forwarding them would move the
+/// source back to offsets that were already assigned a timestamp
+fn byo_check_offset_order(updates: Vec<(u64, i64)>) -> (Vec<(u64, i64)>, Vec<failure::Error>) {
+    let mut accepted = Vec::with_capacity(updates.len());
+    let mut errors = vec![];
+    let mut max_offset = None;
+    for (ts, offset) in updates {
+        match max_offset {
+            Some(max_offset) if offset < max_offset => errors.push(format_err!(
+                "offset {} at timestamp {} is behind offset {} earlier in the batch",
+                offset,
+                ts,
+                max_offset
+            )),
+            _ => {
+                max_offset = Some(offset);
+                accepted.push((ts, offset));
+            }
+        }
+    }
+    (accepted, errors)
+}
+
+/// Derives the timestamp update of a CSV or JSON consistency record, if the record is
+/// about the source's `topic`
+fn byo_ts_update(
+    message: &ConsistencyMessage,
+    connector: &KafkaSourceConnector,
+    topic: &str,
+    format: &ConsistencyFormat,
+) -> Result<Option<(u64, i64)>, failure::Error> {
+    let (topic_name, ts, offset) = byo_decode_message(message, connector, format)?;
+    if topic_name != topic {
+        return Ok(None);
+    }
+    // Normalize to milliseconds, the unit of real-time timestamps
+    let ts = match connector.consistency_timestamp_unit.to_millis(ts) {
+        Some(ts) => ts,
+        None => bail!("timestamp {} overflows when converted to milliseconds", ts),
+    };
+    let ts = correct_timestamp(ts, connector.consistency_timestamp_correction)?;
+    Ok(Some((ts, offset)))
+}
+
+/// Derives the timestamp update of a record of a Debezium transaction metadata topic.
+/// The source is advanced by the number of its events in the transaction, at the time of
+/// the transaction. Timestamps of a source must strictly increase, so a transaction that
+/// is not ahead of the previous timestamp, `last_ts`, is bumped
+fn byo_debezium_ts_update(
+    message: &ConsistencyMessage,
+    connector: &KafkaSourceConnector,
+    topic: &str,
+    last_ts: Option<u64>,
+    transaction_events: &mut i64,
+) -> Result<Option<(u64, i64)>, failure::Error> {
+    let payload = byo_decompress(&message.payload, connector.consistency_compression)?;
+    let transaction = match byo_parse_debezium_transaction(&payload)? {
+        Some(transaction) => transaction,
+        None => return Ok(None),
+    };
+    let event_count = transaction
+        .data_collections
+        .iter()
+        .filter(|(collection, _)| debezium_collection_matches(topic, collection))
+        .map(|(_, count)| count)
+        .sum::<i64>();
+    if event_count == 0 {
+        return Ok(None);
+    }
+    let ts = match transaction.ts_ms.or(message.timestamp) {
+        Some(ts) => ts,
+        None => bail!("transaction record has neither ts_ms nor a Kafka timestamp"),
+    };
+    let ts = correct_timestamp(ts, connector.consistency_timestamp_correction)?;
+    let ts = match last_ts {
+        Some(last_ts) if ts <= last_ts => last_ts + 1,
+        _ => ts,
+    };
+    *transaction_events += event_count;
+    Ok(Some((ts, *transaction_events - 1)))
+}
+
+/// Polls a message from a Kafka Source
+fn get_next_message(consumer: &mut ByoTimestampConsumer) -> Option<ConsistencyMessage> {
+    poll_message(consumer, CONSISTENCY_POLL_TIMEOUT)
+}
+
+/// Reads up to `max_messages` consistency records. Only the first record is waited for:
+/// the others must already have been fetched by the consumer, so that a backlog is read
+/// in large batches rather than with one wait per record
+fn get_next_messages(
+    consumer: &mut ByoTimestampConsumer,
+    max_messages: usize,
+) -> Vec<ConsistencyMessage> {
+    let mut messages = vec![];
+    let mut timeout = CONSISTENCY_POLL_TIMEOUT;
+    while messages.len() < max_messages {
+        match poll_message(consumer, timeout) {
+            Some(message) => messages.push(message),
+            None => break,
+        }
+        timeout = Duration::from_millis(0);
+    }
+    messages
+}
+
+/// Returns the value of the first header of a message with the given name, if any
+fn message_header(message: &BorrowedMessage, name: &str) -> Option<Vec<u8>> {
+    let headers = message.headers()?;
+    (0..headers.count())
+        .filter_map(|i| headers.get(i))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_vec())
+}
+
+/// Polls a message from a Kafka Source, waiting for at most `timeout`
+fn poll_message(
+    consumer: &mut ByoTimestampConsumer,
+    timeout: Duration,
+) -> Option<ConsistencyMessage> {
+    if let Some(result) = consumer.consumer.poll(timeout) {
+        match result {
+            Ok(message) => match message.payload() {
+                Some(p) => {
+                    consumer.consecutive_failures = 0;
+                    Some(ConsistencyMessage {
+                        key: message.key().map(|k| k.to_vec()),
+                        payload: p.to_vec(),
+                        offset: message.offset(),
+                        timestamp: message
+                            .timestamp()
+                            .to_millis()
+                            .filter(|ts| *ts >= 0)
+                            .map(|ts| ts as u64),
+                        format: consumer
+                            .connector
+                            .consistency_format_header
+                            .as_ref()
+                            .and_then(|header| message_header(&message, header)),
+                    })
+                }
+                None => {
+                    error!("unexpected null payload");
+                    None
+                }
+            },
+            Err(err) => {
+                error!("Failed to process message {}", err);
+                consumer.consecutive_failures += 1;
+                None
+            }
+        }
+    } else {
+        None
+    }
+}
+
+/// Returns the offset up to which a RT source is timestamped next, given the last offset
+/// that was timestamped and the last offset of the topic. Bounds the next timestamp to be
+/// no more than `max_increment_size` in the future. The offset never goes backwards, even
+/// if the high watermark does
+fn rt_next_offset(last_offset: i64, high: i64, max_increment_size: i64) -> i64 {
+    if high <= last_offset {
+        last_offset
+    } else if high - last_offset > max_increment_size {
+        last_offset + max_increment_size
+    } else {
+        high
+    }
+}
+
+/// Returns whether a source should be polled in the current pass. Broken sources are only
+/// polled once every `BROKEN_SOURCE_RETRY_INTERVAL`
+fn poll_due(
+    broken: &mut HashMap<SourceInstanceId, Instant>,
+    id: SourceInstanceId,
+    now: Instant,
+) -> bool {
+    match broken.get_mut(&id) {
+        Some(last_poll) if now.duration_since(*last_poll) < BROKEN_SOURCE_RETRY_INTERVAL => false,
+        Some(last_poll) => {
+            *last_poll = now;
+            true
+        }
+        None => true,
+    }
+}
+
+/// Drops the rows of a timestamp history that do not affect the timestamps of messages.
+/// Of consecutive rows with the same offset, the first determines the timestamp of the
+/// messages up to that offset, while the others only advance the source's frontier. Those
+/// are dropped, except for the very last row which determines the final frontier
+fn collapse_history(history: Vec<TimestampRow>) -> Vec<TimestampRow> {
+    let last = history.last().cloned();
+    let mut collapsed: Vec<TimestampRow> = vec![];
+    for row in history {
+        if collapsed
+            .last()
+            .map_or(true, |prev| prev.offset != row.offset)
+        {
+            collapsed.push(row);
+        }
+    }
+    if let Some(last) = last {
+        if collapsed.last() != Some(&last) {
+            collapsed.push(last);
+        }
+    }
+    collapsed
+}
+
+/// Locks the timestamp storage. A poisoned lock is recovered rather than propagated, as a
+/// panic elsewhere while holding the lock leaves the SQLite connection usable
+fn lock_storage(storage: &Mutex<catalog::sql::Connection>) -> MutexGuard<catalog::sql::Connection> {
+    storage.lock().unwrap_or_else(|poisoned| {
+        warn!("Timestamp storage lock was poisoned. Recovering the connection");
+        poisoned.into_inner()
+    })
+}
+
+/// Returns whether a storage error is caused by the process running out of file
+/// descriptors, which SQLite reports as a failure to open a file
+fn is_fd_exhaustion(err: &rusqlite::Error) -> bool {
+    match err {
+        rusqlite::Error::SqliteFailure(err, _) => err.code == rusqlite::ErrorCode::CannotOpen,
+        _ => false,
+    }
+}
+
+/// Returns the number of file descriptors the process has open, where it can be found
+fn open_fd_count() -> Option<usize> {
+    std::fs::read_dir("/proc/self/fd")
+        .ok()
+        .map(|entries| entries.count())
+}
+
+/// Builds the Kafka client configuration shared by the RT and BYO consumers: where to
+/// connect, and how
+fn kafka_client_config(
+    group_id: &str,
+    connector: &KafkaSourceConnector,
+    client_rack: Option<&str>,
+) -> ClientConfig {
+    let mut config = ClientConfig::new();
+    config
+        .set("group.id", group_id)
+        .set("enable.auto.commit", "false")
+        .set("enable.sparse.connections", "true")
+        .set("bootstrap.servers", &connector.url.to_string());
+
+    if let Some(rack) = client_rack {
+        // Lets brokers that support it serve fetches from the closest replica
+        config.set("client.rack", rack);
+    }
+
+    if let Some(path) = &connector.ssl_certificate_file {
+        set_ssl_certificate(&mut config, path);
+    }
+    config
+}
+
+/// Connects a Kafka client over SSL, trusting the certificate at `path`
+fn set_ssl_certificate(config: &mut ClientConfig, path: &Path) {
+    config.set("security.protocol", "ssl");
+    config.set(
+        "ssl.ca.location",
+        path.to_str()
+            .expect("Converting ssl certificate file path failed"),
+    );
+}
+
+/// Converts the Kafka timestamp `event_ms` of a message into a timestamp of an event time
+/// source. Like generated timestamps, it is relative to `epoch_ms` and never ahead of the
+/// current timestamp `clock_ts`. It is bumped past the source's previous timestamp
+/// `last_ts`, and is `None` if that takes it past `ceiling`
+fn event_time_timestamp(
+    event_ms: u64,
+    epoch_ms: u64,
+    clock_ts: u64,
+    last_ts: Option<u64>,
+    ceiling: u64,
+) -> Option<u64> {
+    let ts = event_ms.saturating_sub(epoch_ms).min(clock_ts);
+    let ts = match last_ts {
+        Some(last_ts) if ts <= last_ts => last_ts.checked_add(1)?,
+        _ => ts,
+    };
+    if ts > ceiling {
+        None
+    } else {
+        Some(ts)
+    }
+}
+
+/// Builds the Kafka client configuration of a consumer that queries the watermarks of a
+/// RT source. Watermarks are fetched with offset requests, which none of the fetch and
+/// queueing settings affect, so none are set
+fn rt_kafka_client_config(
+    group_id: &str,
+    connector: &KafkaSourceConnector,
+    client_rack: Option<&str>,
+) -> ClientConfig {
+    let mut config = kafka_client_config(group_id, connector, client_rack);
+    apply_config_options(&mut config, &connector.config_options, &connector.topic);
+    config
+}
+
+/// Builds the Kafka client configuration of a consumer that reads the Kafka timestamps of
+/// messages of an event time source. Only one message is read at a time, so prefetching
+/// is kept to a minimum
+fn event_time_kafka_client_config(
+    group_id: &str,
+    connector: &KafkaSourceConnector,
+    client_rack: Option<&str>,
+) -> ClientConfig {
+    let mut config = kafka_client_config(group_id, connector, client_rack);
+    config
+        .set("queued.min.messages", "1")
+        .set("queued.max.messages.kbytes", "1024")
+        .set("fetch.wait.max.ms", "10");
+    apply_config_options(&mut config, &connector.config_options, &connector.topic);
+    config
+}
+
+/// Builds the Kafka client configuration of a consumer of a consistency topic. The
+/// consistency topic's brokers and certificate default to those of the data topic
+fn byo_kafka_client_config(
+    group_id: &str,
+    connector: &KafkaSourceConnector,
+    client_rack: Option<&str>,
+) -> ClientConfig {
+    let mut config = kafka_client_config(group_id, connector, client_rack);
+    config
+        .set("auto.offset.reset", "earliest")
+        .set("enable.partition.eof", "false")
+        .set("session.timeout.ms", "300000")
+        .set("max.poll.interval.ms", "300000") // 5 minutes
+        .set("fetch.message.max.bytes", "134217728");
+    if let Some(url) = &connector.consistency_url {
+        config.set("bootstrap.servers", &url.to_string());
+    }
+    if let Some(path) = &connector.consistency_ssl_certificate_file {
+        set_ssl_certificate(&mut config, path);
+    }
+    config.set(
+        "isolation.level",
+        if connector.consistency_read_committed {
+            "read_committed"
+        } else {
+            "read_uncommitted"
+        },
+    );
+    apply_config_options(&mut config, &connector.config_options, &connector.topic);
+    config
+}
+
+/// Applies the additional rdkafka properties of the client of `topic` on top of its own
+/// settings. Protected properties are rejected when the source is created, but are also
+/// skipped here, so that they can never be overridden
+fn apply_config_options(
+    config: &mut ClientConfig,
+    config_options: &HashMap<String, String>,
+    topic: &str,
+) {
+    for (key, value) in config_options {
+        if PROTECTED_KAFKA_OPTIONS.contains(&key.as_str()) {
+            warn!(
+                "Ignoring rdkafka property {} of {}: it cannot be overridden",
+                key, topic
+            );
+            continue;
+        }
+        config.set(key, value);
+    }
+}
+
+/// Checks that the certificate file of a Kafka connection, if any, can be read. librdkafka
+/// only reports a missing certificate with an opaque error, much later on
+fn check_certificate_file(path: Option<&Path>) -> Result<(), failure::Error> {
+    if let Some(path) = path {
+        if let Err(e) = File::open(path) {
+            bail!(
+                "Cannot read ssl certificate file {}: {}. Check the ssl_certificate_file option of the source",
+                path.display(),
+                e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Returns the largest persisted timestamp, if any
+fn persisted_max_timestamp(storage: &Mutex<catalog::sql::Connection>) -> Option<u64> {
+    lock_storage(storage)
+        .prepare(
+            "SELECT max(timestamp) FROM \
+             (SELECT timestamp FROM timestamps UNION ALL SELECT timestamp FROM checkpoints)",
+        )
+        .expect("Failed to prepare statement")
+        .query_row(NO_PARAMS, |row| row.get::<_, Option<i64>>(0))
+        .expect("Failed to execute select statement")
+        .map(|ts| ts as u64)
+}
+
+/// Checks that the timestamps persisted in `storage` were generated relative to the epoch
+/// of `config`
+pub(crate) fn check_persisted_epoch(
+    config: &TimestampConfig,
+    storage: &Mutex<catalog::sql::Connection>,
+) -> Result<(), failure::Error> {
+    let has_history = persisted_max_timestamp(storage).is_some();
+    check_epoch(storage, config.epoch.as_millis() as u64, has_history)
+}
+
+/// Checks that persisted timestamps were generated relative to the configured epoch, and
+/// records the epoch if it was never recorded. Timestamps persisted before epochs were
+/// recorded are relative to the Unix epoch
+fn check_epoch(
+    storage: &Mutex<catalog::sql::Connection>,
+    epoch_ms: u64,
+    has_history: bool,
+) -> Result<(), failure::Error> {
+    let storage = lock_storage(storage);
+    let persisted = match storage
+        .prepare("SELECT epoch FROM timestamp_epoch")
+        .expect("Failed to prepare select statement")
+        .query_row(NO_PARAMS, |row| {
+            let epoch: i64 = row.get(0)?;
+            Ok(epoch as u64)
+        }) {
+        Ok(epoch) => epoch,
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            let epoch = if has_history { 0 } else { epoch_ms };
+            storage
+                .prepare("INSERT INTO timestamp_epoch (epoch) VALUES (?)")
+                .expect("Failed to prepare insert statement")
+                .execute(params![epoch as i64])
+                .expect("Failed to record timestamp epoch");
+            epoch
+        }
+        Err(e) => panic!("Failed to parse SQL result: {}", e),
+    };
+    if persisted != epoch_ms {
+        bail!(
+            "Persisted timestamps are relative to an epoch of {} ms, but the configured epoch is {} ms",
+            persisted, epoch_ms
+        );
+    }
+    Ok(())
+}
+
+/// Returns the certificate file used to connect to the consistency topic, if any
+fn byo_certificate_file(connector: &KafkaSourceConnector) -> Option<&Path> {
+    connector
+        .consistency_ssl_certificate_file
+        .as_deref()
+        .or_else(|| connector.ssl_certificate_file.as_deref())
+}
+
+/// Creates the Kafka consumer used to query the watermarks of a RT source
+fn create_rt_kafka_consumer(
+    group_id: &str,
+    connector: &KafkaSourceConnector,
+    client_rack: Option<&str>,
+) -> Result<BaseConsumer, failure::Error> {
+    check_certificate_file(connector.ssl_certificate_file.as_deref())?;
+    Ok(rt_kafka_client_config(group_id, connector, client_rack).create()?)
+}
+
+/// Creates the Kafka consumer used to read the Kafka timestamps of messages of an event
+/// time source
+fn create_event_time_kafka_consumer(
+    group_id: &str,
+    connector: &KafkaSourceConnector,
+    client_rack: Option<&str>,
+) -> Result<BaseConsumer, failure::Error> {
+    check_certificate_file(connector.ssl_certificate_file.as_deref())?;
+    Ok(event_time_kafka_client_config(group_id, connector, client_rack).create()?)
+}
+
+/// Creates the Kafka consumer used to read the consistency topic of a BYO source. If
+/// `consistency_offset` is set, consumption resumes from the record that follows it
+fn create_byo_kafka_consumer(
+    group_id: &str,
+    connector: &KafkaSourceConnector,
+    client_rack: Option<&str>,
+    timestamp_topic: &str,
+    consistency_offset: Option<i64>,
+) -> Result<BaseConsumer, failure::Error> {
+    check_certificate_file(byo_certificate_file(connector))?;
+    let consumer: BaseConsumer =
+        byo_kafka_client_config(group_id, connector, client_rack).create()?;
+
+    let resume_offset = consistency_offset.and_then(|offset| {
+        let resume_offset = offset + 1;
+        match consumer.fetch_watermarks(timestamp_topic, 0, Duration::from_secs(1)) {
+            Ok((low, high)) if low <= resume_offset && resume_offset <= high => Some(resume_offset),
+            Ok((low, high)) => {
+                warn!(
+                    "Persisted offset {} for consistency topic {} is outside of [{}, {}]. \
+                     Reading from earliest instead.",
+                    offset, timestamp_topic, low, high
+                );
+                None
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to obtain Kafka Watermark Information for consistency topic {}: {}. \
+                     Reading from earliest instead.",
+                    timestamp_topic, e
+                );
+                None
+            }
+        }
+    });
+    // Brokers may be briefly unavailable when a source is added
+    let mut attempt = 1;
+    loop {
+        let result = match resume_offset {
+            Some(offset) => {
+                let mut partitions = TopicPartitionList::new();
+                partitions.add_partition_offset(timestamp_topic, 0, Offset::Offset(offset));
+                consumer.assign(&partitions)
+            }
+            None => consumer.subscribe(&[timestamp_topic]),
+        };
+        match result {
+            Ok(()) => return Ok(consumer),
+            Err(e) if attempt < SUBSCRIBE_ATTEMPTS => {
+                warn!(
+                    "Failed to subscribe to consistency topic {} (attempt {} of {}): {}",
+                    timestamp_topic, attempt, SUBSCRIBE_ATTEMPTS, e
+                );
+                std::thread::sleep(SUBSCRIBE_RETRY_INTERVAL * attempt);
+                attempt += 1;
+            }
+            Err(e) => bail!(
+                "failed to subscribe to consistency topic {} after {} attempts: {}",
+                timestamp_topic,
+                SUBSCRIBE_ATTEMPTS,
+                e
+            ),
+        }
+    }
+}
+
+/// A record of a timestamp update that was sent to the coordinator
+#[derive(Serialize)]
+struct AuditRecord {
+    source_id: String,
+    timestamp: u64,
+    offset: i64,
+}
+
+/// Mirrors timestamp updates to a Kafka topic. Records are produced without waiting for
+/// their delivery, and failures are only logged, so that the sink never holds up
+/// timestamping
+struct AuditSink {
+    producer: BaseProducer,
+    topic: String,
+    // Number of records that could not be produced since the last report
+    failures: Cell<u64>,
+    last_report: Cell<Instant>,
+}
+
+impl AuditSink {
+    fn new(config: &TimestampAuditConfig) -> KafkaResult<AuditSink> {
+        // Connects like the consumers of sources, with the same certificate handling and
+        // additional properties
+        let mut client_config = ClientConfig::new();
+        client_config.set("bootstrap.servers", &config.url.to_string());
+        if let Some(path) = &config.ssl_certificate_file {
+            set_ssl_certificate(&mut client_config, path);
+        }
+        apply_config_options(&mut client_config, &config.config_options, &config.topic);
+        let producer = client_config.create()?;
+        Ok(AuditSink {
+            producer,
+            topic: config.topic.clone(),
+            failures: Cell::new(0),
+            last_report: Cell::new(Instant::now()),
+        })
+    }
+
+    fn mirror(&self, id: SourceInstanceId, timestamp: u64, offset: i64) {
+        let source_id = id.to_string();
+        let payload = serde_json::to_vec(&AuditRecord {
+            source_id: source_id.clone(),
+            timestamp,
+            offset,
+        })
+        .expect("audit record serialization cannot fail");
+        let record = BaseRecord::to(&self.topic)
+            .key(&source_id)
+            .payload(&payload);
+        if self.producer.send(record).is_err() {
+            self.failures.set(self.failures.get() + 1);
+        }
+        // Serves delivery reports, without blocking
+        self.producer.poll(Duration::from_millis(0));
+        if self.failures.get() > 0 && self.last_report.get().elapsed() >= REJECTION_LOG_INTERVAL {
+            warn!(
+                "Failed to mirror {} timestamp updates to audit topic {}",
+                self.failures.get(),
+                self.topic
+            );
+            self.failures.set(0);
+            self.last_report.set(Instant::now());
+        }
+    }
+}
+
+pub struct Timestamper {
+    // Current list of up to date sources that use a real time consistency model
+    rt_sources: HashMap<SourceInstanceId, RtTimestampConsumer>,
+
+    // Current list of up to date sources that use a BYO consistency model
+    byo_sources: HashMap<SourceInstanceId, ByoTimestampConsumer>,
+
+    // Connection to the underlying SQL lite instance
+    storage: Arc<Mutex<catalog::sql::Connection>>,
+
+    // Channel with coordinator
+    coord_channel: TimestampChannel,
+
+    // Destination of assigned timestamps
+    sink: Box<dyn TimestampSink>,
+
+    // Source of the wall-clock time that real-time timestamps are derived from
+    clock: Box<dyn FnMut() -> SystemTime + Send>,
+
+    // Notified of the lifecycle events of sources, if any
+    observer: Option<Box<dyn TimestamperObserver>>,
+
+    // Last Timestamp (necessary because not necessarily increasing otherwise)
+    current_timestamp: u64,
+
+    // Frequency at which thread should run
+    timestamp_frequency: Duration,
+
+    // Whether passes only run when the coordinator sends a Tick
+    pull: bool,
+
+    // Whether a Tick was received since the last pass
+    tick_requested: bool,
+
+    // Whether RT passes may reuse the previous timestamp
+    coalesce_timestamps: bool,
+
+    // Rack of the Kafka consumers
+    client_rack: Option<String>,
+
+    // Maximum number of sources that are timestamped at once, if any
+    max_sources: Option<usize>,
+
+    // Epoch of real-time timestamps, in milliseconds since the Unix epoch
+    epoch_ms: u64,
+
+    // Time without consistency records after which a BYO producer is reported as stalled
+    byo_stall_threshold: Duration,
+
+    // Time after a source is added during which it is not reported as stalled
+    startup_grace_period: Duration,
+
+    // Number of consecutive empty polls after which a BYO source is reported as idle
+    byo_idle_cycles: Option<u32>,
+
+    // Number of raw consistency records retained for each BYO source
+    byo_record_history: usize,
+
+    // Minimum time between two writes of assigned timestamps to storage, if any
+    persist_interval: Option<Duration>,
+
+    // Time of the last write of assigned timestamps to storage
+    last_persist: Instant,
+
+    // Timestamps of RT sources that were not written to storage yet
+    pending_rt: Vec<(SourceInstanceId, u64, i64)>,
+
+    // Timestamps and consistency offsets of BYO sources that were not written to storage yet
+    pending_byo: Vec<(SourceInstanceId, Vec<(u64, i64)>, i64, i64)>,
+
+    // Number of timestamping passes between two checkpoints, if any
+    checkpoint_interval: Option<u32>,
+
+    // Number of timestamping passes since the last checkpoint
+    passes_since_checkpoint: u32,
+
+    // Whether recovery only sends the latest persisted timestamp of each source
+    fast_recovery: bool,
+
+    // Largest real-time timestamp that is assigned
+    timestamp_ceiling: u64,
+
+    // Whether the timestamp ceiling has been reported as approaching, and as reached
+    ceiling_approached: bool,
+    ceiling_reached: bool,
+
+    // Whether this is a standby timestamper, which neither persists nor sends timestamps
+    standby: bool,
+
+    // Max increment size
+    max_increment_size: i64,
+
+    // Whether to log key events as JSON lines
+    structured_logging: bool,
+
+    // Kafka group id prefixes of RT and BYO consumers
+    rt_group_id_prefix: String,
+    byo_group_id_prefix: String,
+
+    // Maximum time to wait for the watermarks of a topic
+    watermark_timeout: Duration,
+
+    // Messages received from the coordinator while checking for a shutdown request,
+    // which have yet to be processed
+    pending: RefCell<VecDeque<TimestampMessage>>,
+
+    // Whether the coordinator has requested a shutdown
+    shutting_down: Cell<bool>,
+
+    // Sources that were skipped in the latest timestamping pass, and why
+    skipped: Vec<(SourceInstanceId, String)>,
+
+    // Number of consecutive passes in which each source was skipped
+    skipped_passes: HashMap<SourceInstanceId, u32>,
+
+    // Sources that have been skipped too many times in a row, along with the last time
+    // they were polled. These are only polled every BROKEN_SOURCE_RETRY_INTERVAL
+    broken: HashMap<SourceInstanceId, Instant>,
+
+    // Paused sources whose Kafka consumer was closed to release file descriptors
+    released: HashMap<SourceInstanceId, ReleasedSource>,
+
+    // Topic to which timestamp updates are mirrored, if any
+    audit: Option<AuditSink>,
+}
+
+impl Timestamper {
+    /// Creates a timestamper. Fails if the persisted timestamps were generated relative to
+    /// another epoch than the configured one
+    pub fn new(
+        config: &TimestampConfig,
+        storage: Arc<Mutex<catalog::sql::Connection>>,
+        channel: TimestampChannel,
+    ) -> Result<Self, failure::Error> {
+        // Recover existing data by running max on the timestamp count. This will ensure that
+        // there will never be two duplicate entries and that there is a continuous stream
+        // of timestamp updates across reboots
+        let max_ts = persisted_max_timestamp(&storage);
+        let epoch_ms = config.epoch.as_millis() as u64;
+        check_epoch(&storage, epoch_ms, max_ts.is_some())?;
+        let max_ts = max_ts.unwrap_or(0);
+
+        info!(
+            "Starting Timestamping Thread. Frequency: {} ms.",
+            config.frequency.as_millis()
+        );
+
+        let audit = config.audit.as_ref().and_then(|audit| match AuditSink::new(audit) {
+            Ok(sink) => Some(sink),
+            Err(e) => {
+                error!(
+                    "Failed to create producer for audit topic {}: {}. Timestamp updates will not be mirrored",
+                    audit.topic, e
+                );
+                None
+            }
+        });
+
+        Ok(Self {
+            rt_sources: HashMap::new(),
+            byo_sources: HashMap::new(),
+            storage,
+            sink: Box::new(channel.sender.clone()),
+            clock: Box::new(SystemTime::now),
+            observer: None,
+            coord_channel: channel,
+            current_timestamp: max_ts,
+            timestamp_frequency: config.frequency,
+            pull: config.pull,
+            tick_requested: false,
+            coalesce_timestamps: config.coalesce_timestamps,
+            client_rack: config.client_rack.clone(),
+            max_sources: config.max_sources,
+            epoch_ms,
+            byo_stall_threshold: config.byo_stall_threshold,
+            byo_idle_cycles: config.byo_idle_cycles,
+            byo_record_history: config.byo_record_history,
+            startup_grace_period: config.startup_grace_period,
+            persist_interval: config.persist_interval,
+            last_persist: Instant::now(),
+            pending_rt: vec![],
+            pending_byo: vec![],
+            checkpoint_interval: config.checkpoint_interval,
+            passes_since_checkpoint: 0,
+            fast_recovery: config.fast_recovery,
+            timestamp_ceiling: config.timestamp_ceiling,
+            ceiling_approached: false,
+            ceiling_reached: false,
+            standby: config.standby,
+            max_increment_size: config.max_size,
+            structured_logging: config.structured_logging,
+            rt_group_id_prefix: config.rt_group_id_prefix.clone(),
+            byo_group_id_prefix: config.byo_group_id_prefix.clone(),
+            watermark_timeout: config.watermark_timeout,
+            pending: RefCell::new(VecDeque::new()),
+            shutting_down: Cell::new(false),
+            skipped: vec![],
+            skipped_passes: HashMap::new(),
+            broken: HashMap::new(),
+            released: HashMap::new(),
+            audit,
+        })
+    }
+
+    /// Sends assigned timestamps to `sink` rather than to the coordinator. Other
+    /// notifications, such as `CaughtUp` or `Complete`, still go through the channel
+    pub fn with_sink(mut self, sink: Box<dyn TimestampSink>) -> Self {
+        self.sink = sink;
+        self
+    }
+
+    /// Creates a timestamper whose assigned timestamps are delivered by the returned
+    /// stream rather than sent to the coordinator. The timestamper still receives its
+    /// instructions, and sends other notifications such as `CaughtUp` or `Complete`,
+    /// through `channel`
+    pub fn new_with_stream(
+        config: &TimestampConfig,
+        storage: Arc<Mutex<catalog::sql::Connection>>,
+        channel: TimestampChannel,
+    ) -> Result<(Self, impl Stream<Item = SourceTimestampUpdate>), failure::Error> {
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let timestamper = Self::new(config, storage, channel)?.with_sink(Box::new(tx));
+        Ok((timestamper, rx))
+    }
+
+    /// Notifies `observer` of the lifecycle events of sources
+    pub fn with_observer(mut self, observer: Box<dyn TimestamperObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Derives real-time timestamps from `clock` rather than from the system clock
+    pub fn with_clock(mut self, clock: Box<dyn FnMut() -> SystemTime + Send>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Reads up to `sample_size` records from a consistency topic and checks that they
+    /// parse as consistency records. Nothing is forwarded to the coordinator. The
+    /// consumer is configured like those of BYO sources, from `config`
+    pub fn validate_consistency_topic(
+        config: &TimestampConfig,
+        connector: KafkaSourceConnector,
+        consistency_topic: String,
+        sample_size: usize,
+    ) -> Result<ValidationReport, failure::Error> {
+        check_certificate_file(byo_certificate_file(&connector))?;
+        let group_id = format!(
+            "{}-{}-validate",
+            config.byo_group_id_prefix, consistency_topic
+        );
+        let client_config =
+            byo_kafka_client_config(&group_id, &connector, config.client_rack.as_deref());
+        let mut consumer = ByoTimestampConsumer {
+            consumer: client_config.create()?,
+            group_id,
+            topic: connector.topic.clone(),
+            timestamp_topic: consistency_topic,
+            compacted: connector.consistency_compacted,
+            rejections: RejectionLog::default(),
+            consistency_offset: None,
+            max_increment_size: connector.max_increment_ts_size,
+            last_offset: -1,
+            bounded: false,
+            end_offset: None,
+            end_consumer: None,
+            durable: connector.durable,
+            last_update: None,
+            consecutive_failures: 0,
+            client_rack: config.client_rack.clone(),
+            paused: false,
+            last_record: Instant::now(),
+            stalled: false,
+            empty_cycles: 0,
+            recent_records: VecDeque::new(),
+            record_history: 0,
+            added: Instant::now(),
+            created_at: SystemTime::now(),
+            transaction_events: 0,
+            connector,
+        };
+        consumer.consumer.subscribe(&[&consumer.timestamp_topic])?;
+
+        let mut report = ValidationReport::default();
+        while report.parsed + report.rejected < sample_size {
+            let message = match get_next_message(&mut consumer) {
+                Some(message) => message,
+                None => break,
+            };
+            let format = match byo_record_format(&message, &consumer.connector) {
+                Ok(format) => format,
+                Err(_) => {
+                    report.rejected += 1;
+                    continue;
+                }
+            };
+            if *format == ConsistencyFormat::DebeziumTransaction {
+                let transaction =
+                    byo_decompress(&message.payload, consumer.connector.consistency_compression)
+                        .and_then(|payload| byo_parse_debezium_transaction(&payload));
+                match transaction {
+                    Ok(transaction) => {
+                        report.parsed += 1;
+                        for (collection, _) in
+                            transaction.into_iter().flat_map(|t| t.data_collections)
+                        {
+                            report.source_names.insert(collection);
+                        }
+                    }
+                    Err(_) => report.rejected += 1,
+                }
+                continue;
+            }
+            match byo_decode_message(&message, &consumer.connector, &format) {
+                Ok((source_name, _ts, _offset)) => {
+                    report.parsed += 1;
+                    report.source_names.insert(source_name);
+                }
+                Err(_) => report.rejected += 1,
+            }
+        }
+        Ok(report)
+    }
+
+    fn storage(&self) -> MutexGuard<catalog::sql::Connection> {
+        lock_storage(&self.storage)
+    }
+
+    /// Run the update function in a loop at the specified frequency, or whenever the
+    /// coordinator sends a `Tick` in pull mode. Acquires timestamps using either 1) the
+    /// Kafka topic ground truth 2) real-time
+    pub fn update(&mut self) {
+        loop {
+            if self.pull {
+                self.wait_for_message();
+            } else {
+                self.wait_for_next_pass();
+            }
+            let shutdown = self.update_sources();
+            let tick_requested = std::mem::replace(&mut self.tick_requested, false);
+            if shutdown {
+                break;
+            } else if self.pull && !tick_requested {
+                // Control messages are handled as they arrive, but passes wait for a Tick
+                continue;
+            } else if !self.standby {
+                self.update_rt_timestamp();
+                if !self.shutting_down.get() {
+                    self.update_byo_timestamp();
+                }
+                if !self.shutting_down.get() {
+                    self.maybe_flush_pending_timestamps();
+                }
+                if !self.shutting_down.get() {
+                    self.maybe_checkpoint();
+                }
+            }
+        }
+        self.flush_pending_timestamps();
+    }
+
+    /// Writes the timestamps that were not written to storage yet, once `persist_interval`
+    /// has elapsed since the last write
+    fn maybe_flush_pending_timestamps(&mut self) {
+        if let Some(interval) = self.persist_interval {
+            if self.last_persist.elapsed() >= interval {
+                self.flush_pending_timestamps();
+            }
+        }
+    }
+
+    /// Writes the timestamps that were not written to storage yet
+    fn flush_pending_timestamps(&mut self) {
+        let pending_rt = std::mem::replace(&mut self.pending_rt, vec![]);
+        if !pending_rt.is_empty() {
+            self.rt_write_timestamps(&pending_rt);
+        }
+        let pending_byo = std::mem::replace(&mut self.pending_byo, vec![]);
+        for (id, ts_updates, consistency_offset, transaction_events) in pending_byo {
+            if self.shutting_down.get() {
+                return;
+            }
+            self.byo_write_timestamps(id, &ts_updates, consistency_offset, transaction_events);
+        }
+        self.last_persist = Instant::now();
+    }
+
+    /// Checkpoints the persisted timestamps of every durable source, once every
+    /// `checkpoint_interval` passes
+    fn maybe_checkpoint(&mut self) {
+        let interval = match self.checkpoint_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        self.passes_since_checkpoint += 1;
+        if self.passes_since_checkpoint < interval {
+            return;
+        }
+        self.passes_since_checkpoint = 0;
+        let sources: Vec<_> = self
+            .rt_sources
+            .iter()
+            .filter(|(_, cons)| cons.durable)
+            .map(|(id, _)| *id)
+            .chain(
+                self.byo_sources
+                    .iter()
+                    .filter(|(_, cons)| cons.durable)
+                    .map(|(id, _)| *id),
+            )
+            .collect();
+        for id in sources {
+            self.checkpoint_source(id);
+        }
+    }
+
+    /// Moves the persisted timestamp history of a source into its checkpoint, keeping only
+    /// the rows that determine the timestamps of messages and the latest one. Recovery and
+    /// offset lookups return the same timestamps as before the checkpoint
+    fn checkpoint_source(&self, id: SourceInstanceId) {
+        let history = self.dump_history(id);
+        let latest = match history.last() {
+            Some(latest) => *latest,
+            None => return,
+        };
+        let checkpoint = collapse_history(history.clone());
+        let storage = self.storage();
+        // The checkpoint is written before the history is deleted, so that an interruption
+        // leaves redundant rows behind rather than lose any
+        for row in &checkpoint {
+            storage
+                .prepare_cached(
+                    "INSERT OR REPLACE INTO checkpoints (sid, vid, timestamp, offset) VALUES (?, ?, ?, ?)",
+                )
+                .expect("Failed to prepare insert statement")
+                .execute(params![
+                    SqlVal(&id.sid),
+                    SqlVal(&id.vid),
+                    row.timestamp as i64,
+                    row.offset
+                ])
+                .expect("Failed to write checkpoint");
+        }
+        storage
+            .prepare_cached("DELETE FROM timestamps WHERE sid = ? AND vid = ? AND timestamp <= ?")
+            .expect("Failed to prepare delete statement")
+            .execute(params![
+                SqlVal(&id.sid),
+                SqlVal(&id.vid),
+                latest.timestamp as i64
+            ])
+            .expect("Failed to execute delete statement");
+        // Rows of earlier checkpoints that are now redundant
+        let kept: HashSet<_> = checkpoint.iter().map(|row| row.timestamp).collect();
+        for row in history.iter().filter(|row| !kept.contains(&row.timestamp)) {
+            storage
+                .prepare_cached(
+                    "DELETE FROM checkpoints WHERE sid = ? AND vid = ? AND timestamp = ?",
+                )
+                .expect("Failed to prepare delete statement")
+                .execute(params![
+                    SqlVal(&id.sid),
+                    SqlVal(&id.vid),
+                    row.timestamp as i64
+                ])
+                .expect("Failed to execute delete statement");
+        }
+    }
+
+    /// Drains the messages sent by the coordinator, and returns whether a shutdown has been
+    /// requested. Other messages are set aside for the next call to `update_sources`. This
+    /// lets long-running operations, such as retrying a failed write, give up on shutdown
+    fn shutdown_requested(&self) -> bool {
+        let mut pending = self.pending.borrow_mut();
+        while let Ok(update) = self.coord_channel.receiver.try_recv() {
+            match update {
+                TimestampMessage::Shutdown => self.shutting_down.set(true),
+                update => pending.push_back(update),
+            }
+        }
+        self.shutting_down.get()
+    }
+
+    /// Waits for the timestamp frequency to elapse. A shutdown or forced advance request
+    /// from the coordinator ends the wait early. Other messages are set aside for the next
+    /// call to `update_sources`
+    fn wait_for_next_pass(&self) {
+        let deadline = Instant::now() + self.timestamp_frequency;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.coord_channel.receiver.recv_timeout(timeout) {
+                // The pass that follows is the forced one
+                Ok(TimestampMessage::ForceAdvance) => return,
+                Ok(TimestampMessage::Shutdown) | Err(RecvTimeoutError::Disconnected) => {
+                    self.shutting_down.set(true);
+                    return;
+                }
+                Ok(update) => self.pending.borrow_mut().push_back(update),
+                Err(RecvTimeoutError::Timeout) => return,
+            }
+        }
+    }
+
+    /// Blocks until the coordinator sends a message. The message is set aside for the next
+    /// call to `update_sources`, unless it requests a shutdown
+    fn wait_for_message(&self) {
+        match self.coord_channel.receiver.recv() {
+            Ok(TimestampMessage::Shutdown) | Err(_) => self.shutting_down.set(true),
+            Ok(update) => self.pending.borrow_mut().push_back(update),
+        }
+    }
+
+    /// Pops the next message set aside by `shutdown_requested`, if any
+    fn next_pending(&self) -> Option<TimestampMessage> {
+        self.pending.borrow_mut().pop_front()
+    }
+
+    /// Runs a single timestamping pass immediately. Timestamps assigned by this pass are
+    /// subject to the same monotonicity guarantees as those of the regular loop
+    pub fn force_advance(&mut self) {
+        if self.standby {
+            return;
+        }
+        self.update_rt_timestamp();
+        self.update_byo_timestamp();
+    }
+
+    /// Returns the sources that were skipped in the latest timestamping pass because of
+    /// transient errors, along with the reason
+    pub fn skipped_sources(&self) -> &[(SourceInstanceId, String)] {
+        &self.skipped
+    }
+
+    /// Returns the sources that are considered broken because they have been skipped in
+    /// too many consecutive passes. Those are only polled every once in a while
+    pub fn broken_sources(&self) -> Vec<SourceInstanceId> {
+        self.broken.keys().cloned().collect()
+    }
+
+    /// Returns the progress of every real-time source. The high watermark is the one
+    /// fetched by the latest timestamping pass, so no broker is queried
+    pub fn rt_source_progress(&self) -> Vec<RtSourceProgress> {
+        self.rt_sources
+            .iter()
+            .map(|(id, cons)| RtSourceProgress {
+                source_id: *id,
+                last_offset: cons.last_offset,
+                high_watermark: cons.high_watermark,
+                max_increment_size: cons.max_increment_size.unwrap_or(self.max_increment_size),
+            })
+            .collect()
+    }
+
+    /// Returns the wall-clock time at which each source started being timestamped
+    pub fn source_created_times(&self) -> Vec<(SourceInstanceId, SystemTime)> {
+        self.rt_sources
+            .iter()
+            .map(|(id, cons)| (*id, cons.created_at))
+            .chain(
+                self.byo_sources
+                    .iter()
+                    .map(|(id, cons)| (*id, cons.created_at)),
+            )
+            .collect()
+    }
+
+    /// Returns the sources whose timestamping is paused
+    pub fn paused_sources(&self) -> Vec<SourceInstanceId> {
+        self.rt_sources
+            .iter()
+            .filter(|(_, cons)| cons.paused)
+            .map(|(id, _)| *id)
+            .chain(
+                self.byo_sources
+                    .iter()
+                    .filter(|(_, cons)| cons.paused)
+                    .map(|(id, _)| *id),
+            )
+            .collect()
+    }
+
+    /// Pauses or resumes timestamping of a source. The paused state is persisted, so that
+    /// it survives restarts, and applies to sources that are added later on
+    fn set_paused(&mut self, id: SourceInstanceId, paused: bool) {
+        info!(
+            "{} timestamping of Source {}",
+            if paused { "Pausing" } else { "Resuming" },
+            id
+        );
+        if !paused {
+            self.restore_released_consumer(id);
+        }
+        if let Some(cons) = self.rt_sources.get_mut(&id) {
+            cons.paused = paused;
+        }
+        if let Some(cons) = self.byo_sources.get_mut(&id) {
+            cons.paused = paused;
+            // Time spent paused does not count towards a stall
+            cons.last_record = Instant::now();
+        }
+        if self.standby {
+            // The active timestamper persists the paused state
+            return;
+        }
+        let sql = if paused {
+            "INSERT OR REPLACE INTO paused_sources (sid, vid) VALUES (?, ?)"
+        } else {
+            "DELETE FROM paused_sources WHERE sid = ? AND vid = ?"
+        };
+        self.storage()
+            .prepare_cached(sql)
+            .expect("Failed to prepare paused source statement")
+            .execute(params![SqlVal(&id.sid), SqlVal(&id.vid)])
+            .expect("Failed to execute paused source statement");
+    }
+
+    /// Rewinds a real-time source to the last offset that was assigned a timestamp
+    /// below `ts`, so that the following messages are timestamped again. The persisted
+    /// history from `ts` onwards is deleted, as it is about to be replaced
+    fn rewind(&mut self, id: SourceInstanceId, ts: u64) {
+        if self.standby {
+            return;
+        }
+        let durable = match self.rt_sources.get(&id) {
+            Some(cons) => cons.durable,
+            None => {
+                warn!("Cannot rewind Source {}: not a real-time source", id);
+                return;
+            }
+        };
+        if !durable {
+            warn!(
+                "Cannot rewind Source {}: its timestamp history is not persisted",
+                id
+            );
+            return;
+        }
+        // Timestamps that were not written yet must be truncated as well
+        self.flush_pending_timestamps();
+        let offset = self.truncate_history(id, ts);
+        info!(
+            "Rewinding Source {} to timestamp {} (offset {})",
+            id, ts, offset
+        );
+        if let Some(cons) = self.rt_sources.get_mut(&id) {
+            cons.last_offset = offset;
+            cons.last_update = cons.last_update.map(|(ts, _)| (ts, offset));
+            cons.recovered_offset = None;
+            cons.caught_up = false;
+        }
+    }
+
+    /// Deletes the persisted timestamps of a source from `ts` onwards, including those of
+    /// its checkpoint. Returns the last offset that remains timestamped, or -1 if there is
+    /// none
+    fn truncate_history(&self, id: SourceInstanceId, ts: u64) -> i64 {
+        let history = self.dump_history(id);
+        let offset = history
+            .iter()
+            .filter(|row| row.timestamp < ts)
+            .last()
+            .map_or(-1, |row| row.offset);
+        let storage = self.storage();
+        for table in &["timestamps", "checkpoints"] {
+            storage
+                .prepare_cached(&format!(
+                    "DELETE FROM {} WHERE sid = ? AND vid = ? AND timestamp >= ?",
+                    table
+                ))
+                .expect("Failed to prepare delete statement")
+                .execute(params![SqlVal(&id.sid), SqlVal(&id.vid), ts as i64])
+                .expect("Failed to execute delete statement");
+        }
+        offset
+    }
+
+    /// Returns whether timestamping of a source was paused
+    fn persisted_paused(&self, id: SourceInstanceId) -> bool {
+        self.storage()
+            .prepare_cached("SELECT 1 FROM paused_sources WHERE sid = ? AND vid = ?")
+            .expect("Failed to prepare select statement")
+            .exists(params![SqlVal(&id.sid), SqlVal(&id.vid)])
+            .expect("Failed to execute select statement")
+    }
+
+    /// Records that a source could not be timestamped in the current pass
+    fn skip_source(&mut self, id: SourceInstanceId, reason: String) {
+        SKIPPED_PASSES.with_label_values(&[&id.to_string()]).inc();
+        let skipped_passes = self.skipped_passes.entry(id).or_insert(0);
+        *skipped_passes += 1;
+        if *skipped_passes == MAX_SKIPPED_PASSES {
+            error!(
+                "Source {} was skipped in {} consecutive passes and is now only polled every {} seconds. Last error: {}",
+                id,
+                MAX_SKIPPED_PASSES,
+                BROKEN_SOURCE_RETRY_INTERVAL.as_secs(),
+                reason
+            );
+            self.broken.insert(id, Instant::now());
+            if let Some(observer) = &self.observer {
+                observer.on_source_broken(id, &reason);
+            }
+        }
+        self.skipped.push((id, reason));
+    }
+
+    /// Records that a source was successfully polled in the current pass
+    fn source_succeeded(&mut self, id: SourceInstanceId) {
+        SKIPPED_PASSES.with_label_values(&[&id.to_string()]).set(0);
+        self.skipped_passes.remove(&id);
+        if self.broken.remove(&id).is_some() {
+            info!(
+                "Source {} has recovered and is polled on every pass again",
+                id
+            );
+            if let Some(observer) = &self.observer {
+                observer.on_source_healthy(id);
+            }
+        }
+    }
+
+    /// Resets the failure tracking of a source, so that it is polled on every pass again
+    fn retry_source(&mut self, id: SourceInstanceId) {
+        info!("Retrying Source {}", id);
+        SKIPPED_PASSES.with_label_values(&[&id.to_string()]).set(0);
+        self.skipped_passes.remove(&id);
+        if self.broken.remove(&id).is_some() {
+            if let Some(observer) = &self.observer {
+                observer.on_source_healthy(id);
+            }
+        }
+    }
+
+    /// Implements the real-time timestamping logic
+    fn update_rt_timestamp(&mut self) {
+        // A pass starts with the RT sources, followed by the BYO sources
+        self.skipped.clear();
+        if self.current_timestamp >= self.timestamp_ceiling {
+            // Offsets must not be queried unless they can be assigned a new timestamp
+            if !self.ceiling_reached {
+                self.ceiling_reached = true;
+                error!(
+                    "Timestamp ceiling {} reached. Real-time sources no longer advance until the ceiling is raised",
+                    self.timestamp_ceiling
+                );
+            }
+            return;
+        }
+        let (watermarks, caught_up_changes) = self.rt_query_sources();
+        let (event_time_watermarks, watermarks): (Vec<_>, Vec<_>) =
+            watermarks.into_iter().partition(|(id, _)| {
+                self.rt_sources
+                    .get(id)
+                    .map_or(false, |cons| cons.event_time)
+            });
+        // Event time sources only succeed once the event time of their offset is read
+        for (id, _) in &watermarks {
+            self.source_succeeded(*id);
+        }
+        self.rt_generate_next_timestamp();
+        let mut ts_updates: Vec<_> = watermarks
+            .iter()
+            .map(|(id, offset)| (*id, self.current_timestamp, *offset))
+            .collect();
+        let event_time_updates = self.rt_event_time_updates(event_time_watermarks);
+        ts_updates.extend(event_time_updates.iter().cloned());
+        for (id, ts, offset) in &ts_updates {
+            if let Some(cons) = self.rt_sources.get_mut(id) {
+                cons.last_update = Some((*ts, *offset));
+            }
+        }
+        self.rt_persist_timestamp(&ts_updates);
+        if self.shutting_down.get() {
+            return;
+        }
+        for (id, ts, offset) in &ts_updates {
+            log_event(
+                self.structured_logging,
+                Level::Debug,
+                &TimestampEvent::TimestampAdvanced {
+                    source_id: id.to_string(),
+                    timestamp: *ts,
+                    offset: *offset,
+                },
+                format_args!(
+                    "Timestamped Source {} up to offset {} at {}",
+                    id, offset, ts
+                ),
+            );
+        }
+        let mut live = vec![];
+        for (id, _, offset) in &ts_updates {
+            if let Some(cons) = self.rt_sources.get_mut(id) {
+                if cons
+                    .recovered_offset
+                    .map_or(false, |recovered| *offset > recovered)
+                {
+                    cons.recovered_offset = None;
+                    live.push((*id, *offset));
+                }
+            }
+        }
+        self.rt_notify_coordinator(watermarks);
+        for (id, ts, offset) in event_time_updates {
+            self.send_update(id, ts, offset);
+        }
+        for (id, offset) in live {
+            log_event(
+                self.structured_logging,
+                Level::Info,
+                &TimestampEvent::SourceLive {
+                    source_id: id.to_string(),
+                    offset,
+                },
+                format_args!("Source {} is live past offset {}", id, offset),
+            );
+            self.coord_channel
+                .sender
+                .send(TimestampMessage::Live(id))
+                .expect("Failed to send live notice to coordinator");
+        }
+        for (id, caught_up) in caught_up_changes {
+            self.coord_channel
+                .sender
+                .send(TimestampMessage::CaughtUp(id, caught_up))
+                .expect("Failed to send caught up notice to coordinator");
+        }
+        // Bounded sources are complete once they have been timestamped up to their end
+        let complete: Vec<_> = self
+            .rt_sources
+            .iter()
+            .filter(|(_, cons)| cons.is_complete())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in complete {
+            self.retire_source(id);
+        }
+    }
+
+    /// Stops timestamping a source whose bounded topic has been fully timestamped, and
+    /// notifies the coordinator. Unlike dropping the source, persisted state is kept
+    fn retire_source(&mut self, id: SourceInstanceId) {
+        info!("Timestamping complete for bounded Source {}", id);
+        self.rt_sources.remove(&id);
+        self.byo_sources.remove(&id);
+        self.skipped_passes.remove(&id);
+        self.broken.remove(&id);
+        self.coord_channel
+            .sender
+            .send(TimestampMessage::Complete(id))
+            .expect("Failed to send completion notice to coordinator");
+        if let Some(observer) = &self.observer {
+            observer.on_source_dropped(id);
+        }
+    }
+
+    /// Updates list of timestamp sources based on coordinator information. If using
+    /// using the real-time timestamping logic, then maintain a list of Kafka consumers
+    /// that poll topics to check how much data has been generated. If using the Kafka
+    /// source timestamping logic, then keep a mapping of (name,id) to translate user-
+    /// defined timestamps to GlobalIds
+    fn update_sources(&mut self) -> bool {
+        // First check if there are some new source that we should
+        // start checking
+        self.shutdown_requested();
+        while let Some(update) = self.next_pending() {
+            match update {
+                TimestampMessage::Add(id, connector, consistency) => {
+                    if !self.rt_sources.contains_key(&id) && !self.byo_sources.contains_key(&id) {
+                        // Did not know about source, must update
+                        let sources = self.rt_sources.len() + self.byo_sources.len();
+                        if let Some(max_sources) = self.max_sources.filter(|max| sources >= *max) {
+                            let reason = format!(
+                                "already timestamping {} sources, the maximum is {}",
+                                sources, max_sources
+                            );
+                            self.coord_channel
+                                .sender
+                                .send(TimestampMessage::AddRejected(id, reason))
+                                .expect("Failed to send rejection notice to coordinator");
+                            continue;
+                        }
+                        match consistency {
+                            Consistency::RealTime => {
+                                log_event(
+                                    self.structured_logging,
+                                    Level::Info,
+                                    &TimestampEvent::SourceAdded {
+                                        source_id: id.to_string(),
+                                        topic: &connector.topic,
+                                        consistency_topic: None,
+                                    },
+                                    format_args!(
+                                        "Timestamping Source {} with Real Time Consistency",
+                                        id
+                                    ),
+                                );
+                                let mut consumer = match self.create_rt_connector(id, connector, -1)
+                                {
+                                    Ok(consumer) => consumer,
+                                    Err(e) => {
+                                        error!(
+                                            "Failed to create Kafka consumer for Source {}: {}",
+                                            id, e
+                                        );
+                                        self.coord_channel
+                                            .sender
+                                            .send(TimestampMessage::AddRejected(id, e.to_string()))
+                                            .expect(
+                                                "Failed to send rejection notice to coordinator",
+                                            );
+                                        continue;
+                                    }
+                                };
+                                // Non-durable sources have nothing to recover, and a
+                                // standby recovers sources once promoted
+                                if consumer.durable && !self.standby {
+                                    let last_offset = self.recover_source(id);
+                                    consumer.last_offset = last_offset;
+                                    consumer.recovered_offset = Some(last_offset);
+                                }
+                                if consumer.event_time && consumer.durable {
+                                    // Event time timestamps must keep increasing across restarts
+                                    consumer.last_update = self.latest_persisted_update(id);
+                                }
+                                self.rt_sources.insert(id, consumer);
+                                if let Some(observer) = &self.observer {
+                                    observer.on_source_added(id);
+                                }
+                            }
+                            Consistency::BringYourOwn(consistency_topic) => {
+                                log_event(
+                                    self.structured_logging,
+                                    Level::Info,
+                                    &TimestampEvent::SourceAdded {
+                                        source_id: id.to_string(),
+                                        topic: &connector.topic,
+                                        consistency_topic: Some(&consistency_topic),
+                                    },
+                                    format_args!("Timestamping Source {} with BYO Consistency. Topic: {}, Consistency Topic: {}", id, connector.topic, consistency_topic),
+                                );
+                                let recover = connector.durable && !self.standby;
+                                let recovered = if recover {
+                                    self.byo_recover_consistency_offset(id)
+                                } else {
+                                    None
+                                };
+                                match self.create_byo_connector(
+                                    id,
+                                    connector,
+                                    consistency_topic,
+                                    recovered.map(|(offset, _)| offset),
+                                ) {
+                                    Ok(mut consumer) => {
+                                        if recover {
+                                            consumer.last_offset = self.recover_source(id);
+                                            // Timestamps derived from Debezium transactions
+                                            // keep increasing across restarts
+                                            consumer.last_update = self.latest_persisted_update(id);
+                                        }
+                                        // Offsets derived from Debezium transactions
+                                        // continue from the persisted event count
+                                        if let Some((_, transaction_events)) = recovered {
+                                            consumer.transaction_events = transaction_events;
+                                        }
+                                        self.byo_sources.insert(id, consumer);
+                                        if let Some(observer) = &self.observer {
+                                            observer.on_source_added(id);
+                                        }
+                                    }
+                                    Err(e) => {
+                                        error!(
+                                            "Failed to create Kafka consumer for Source {}: {}",
+                                            id, e
+                                        );
+                                        self.coord_channel
+                                            .sender
+                                            .send(TimestampMessage::AddRejected(id, e.to_string()))
+                                            .expect(
+                                                "Failed to send rejection notice to coordinator",
+                                            );
+                                    }
+                                }
+                            }
+                        }
+                    } else if self.config_differs(id, &connector, &consistency) {
+                        warn!(
+                            "Ignoring re-add of source {} with a different configuration. \
+                             The source is still timestamped using its original connector and consistency",
+                            id
+                        );
+                    }
+                }
+                TimestampMessage::DropInstance(id) => {
+                    log_event(
+                        self.structured_logging,
+                        Level::Info,
+                        &TimestampEvent::SourceDropped {
+                            source_id: id.to_string(),
+                        },
+                        format_args!("Dropping Timestamping for Source {}", id),
+                    );
+                    // The active timestamper cleans up the persisted state
+                    if !self.standby {
+                        self.storage()
+                            .prepare_cached("DELETE FROM timestamps WHERE sid = ? AND vid = ?")
+                            .expect("Failed to prepare delete statement")
+                            .execute(params![SqlVal(&id.sid), SqlVal(&id.vid)])
+                            .expect("Failed to execute delete statement");
+                        self.storage()
+                            .prepare_cached("DELETE FROM checkpoints WHERE sid = ? AND vid = ?")
+                            .expect("Failed to prepare delete statement")
+                            .execute(params![SqlVal(&id.sid), SqlVal(&id.vid)])
+                            .expect("Failed to execute delete statement");
+                        self.storage()
+                            .prepare_cached(
+                                "DELETE FROM consistency_offsets WHERE sid = ? AND vid = ?",
+                            )
+                            .expect("Failed to prepare delete statement")
+                            .execute(params![SqlVal(&id.sid), SqlVal(&id.vid)])
+                            .expect("Failed to execute delete statement");
+                        self.storage()
+                            .prepare_cached("DELETE FROM paused_sources WHERE sid = ? AND vid = ?")
+                            .expect("Failed to prepare delete statement")
+                            .execute(params![SqlVal(&id.sid), SqlVal(&id.vid)])
+                            .expect("Failed to execute delete statement");
+                    }
+                    // Pending timestamps would otherwise be written after the cleanup
+                    self.pending_rt
+                        .retain(|(pending_id, _, _)| *pending_id != id);
+                    self.pending_byo
+                        .retain(|(pending_id, ..)| *pending_id != id);
+                    self.rt_sources.remove(&id);
+                    self.byo_sources.remove(&id);
+                    self.released.remove(&id);
+                    self.skipped_passes.remove(&id);
+                    self.broken.remove(&id);
+                    let _ = SKIPPED_PASSES.remove_label_values(&[&id.to_string()]);
+                    let _ = STALLED_PRODUCERS.remove_label_values(&[&id.to_string()]);
+                    if let Some(observer) = &self.observer {
+                        observer.on_source_dropped(id);
+                    }
+                }
+                TimestampMessage::ForceAdvance => self.force_advance(),
+                TimestampMessage::Resync => self.resync(),
+                TimestampMessage::RetrySource(id) => self.retry_source(id),
+                TimestampMessage::Pause(id) => self.set_paused(id, true),
+                TimestampMessage::Resume(id) => self.set_paused(id, false),
+                TimestampMessage::Promote => self.promote(),
+                TimestampMessage::Rewind(id, ts) => self.rewind(id, ts),
+                TimestampMessage::Tick => self.tick_requested = true,
+                _ => {
+                    // this should never happen
+                }
+            }
+        }
+        self.shutting_down.get()
+    }
+
+    /// Sends the latest timestamp of every active source to the coordinator again. Sources
+    /// that have not advanced since they were added fall back to their persisted history
+    fn resync(&mut self) {
+        if self.standby {
+            return;
+        }
+        let sources: Vec<_> = self
+            .rt_sources
+            .iter()
+            .map(|(id, cons)| (*id, cons.last_update))
+            .chain(
+                self.byo_sources
+                    .iter()
+                    .map(|(id, cons)| (*id, cons.last_update)),
+            )
+            .collect();
+        info!("Resyncing timestamps of {} sources", sources.len());
+        for (id, last_update) in sources {
+            let last_update = last_update.or_else(|| self.latest_persisted_update(id));
+            if let Some((ts, offset)) = last_update {
+                self.send_update(id, ts, offset);
+            }
+        }
+    }
+
+    /// Turns a standby timestamper into the active one. Sources are recovered from the
+    /// timestamps persisted by the previously active timestamper, so that timestamping
+    /// resumes right after them
+    fn promote(&mut self) {
+        if !self.standby {
+            return;
+        }
+        info!("Promoting standby timestamper");
+        self.standby = false;
+        if let Some(max_ts) = persisted_max_timestamp(&self.storage) {
+            self.current_timestamp = self.current_timestamp.max(max_ts);
+        }
+        for cons in self.byo_sources.values_mut() {
+            // Consistency topics are not read while on standby, and promotion starts a
+            // new recovery phase
+            cons.last_record = Instant::now();
+            cons.added = Instant::now();
+        }
+        let rt_sources: Vec<_> = self
+            .rt_sources
+            .iter()
+            .filter(|(_, cons)| cons.durable)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in rt_sources {
+            let last_offset = self.recover_source(id);
+            let last_update = self.latest_persisted_update(id);
+            let paused = self.persisted_paused(id);
+            if let Some(cons) = self.rt_sources.get_mut(&id) {
+                cons.last_offset = last_offset;
+                cons.recovered_offset = Some(last_offset);
+                cons.paused = paused;
+                if cons.event_time {
+                    cons.last_update = last_update;
+                }
+            }
+        }
+        let byo_sources: Vec<_> = self
+            .byo_sources
+            .iter()
+            .filter(|(_, cons)| cons.durable)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in byo_sources {
+            let last_offset = self.recover_source(id);
+            let last_update = self.latest_persisted_update(id);
+            let recovered = self.byo_recover_consistency_offset(id);
+            let paused = self.persisted_paused(id);
+            if let Some(cons) = self.byo_sources.get_mut(&id) {
+                cons.last_offset = last_offset;
+                cons.last_update = last_update;
+                cons.consistency_offset = recovered.map(|(offset, _)| offset);
+                cons.transaction_events = recovered.map_or(0, |(_, events)| events);
+                cons.paused = paused;
+                cons.recreate_consumer(id);
+            }
+        }
+    }
+
+    /// Returns the persisted timestamp update with the largest timestamp for `id`, if any
+    fn latest_persisted_update(&self, id: SourceInstanceId) -> Option<(u64, i64)> {
+        self.dump_history(id)
+            .last()
+            .map(|row| (row.timestamp, row.offset))
+    }
+
+    /// Returns whether the source `id` is currently timestamped with a connector or
+    /// consistency other than the given ones
+    fn config_differs(
+        &self,
+        id: SourceInstanceId,
+        connector: &KafkaSourceConnector,
+        consistency: &Consistency,
+    ) -> bool {
+        if let Some(cons) = self.rt_sources.get(&id) {
+            cons.connector != *connector || *consistency != Consistency::RealTime
+        } else if let Some(cons) = self.byo_sources.get(&id) {
+            cons.connector != *connector
+                || *consistency != Consistency::BringYourOwn(cons.timestamp_topic.clone())
+        } else {
+            false
+        }
+    }
+
+    /// Implements the byo timestamping logic
+    fn update_byo_timestamp(&mut self) {
+        let mut byo_updates = vec![];
+        let mut skipped = vec![];
+        let mut succeeded = vec![];
+        let now = Instant::now();
+        let deadline = now + self.timestamp_frequency / BYO_QUERY_BUDGET_FRACTION;
+        for (id, byo_consumer) in &mut self.byo_sources {
+            if byo_consumer.paused || !poll_due(&mut self.broken, *id, now) {
+                continue;
+            }
+            byo_consumer.query_end_offset(self.watermark_timeout);
+            // Get the next set of messages from the Consistency topic
+            let messages = byo_query_source(
+                byo_consumer,
+                self.max_increment_size,
+                deadline,
+                self.watermark_timeout,
+            );
+            if byo_consumer.consecutive_failures > 0 {
+                skipped.push(*id);
+            } else {
+                succeeded.push(*id);
+            }
+            if byo_consumer.consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
+                byo_consumer.reconnect(*id);
+            }
+            // Poll failures are reported separately, so only an idle but reachable
+            // consistency topic counts as a stalled producer
+            if byo_consumer.consecutive_failures == 0 {
+                byo_consumer.check_stalled(
+                    *id,
+                    !messages.is_empty(),
+                    self.byo_stall_threshold,
+                    self.startup_grace_period,
+                    self.structured_logging,
+                );
+                byo_consumer.check_idle(*id, !messages.is_empty(), self.byo_idle_cycles);
+            }
+            if messages.is_empty() {
+                continue;
+            }
+            retain_recent_records(
+                &mut byo_consumer.recent_records,
+                &messages,
+                byo_consumer.record_history,
+            );
+            // Extract the timestamp updates for this topic only
+            let ts_updates = byo_extract_ts_update(byo_consumer, messages);
+            if let Some(last_update) = ts_updates.last() {
+                byo_consumer.last_update = Some(*last_update);
+            }
+            if let Some(max_offset) = ts_updates.iter().map(|(_, offset)| *offset).max() {
+                byo_consumer.last_offset = byo_consumer.last_offset.max(max_offset);
+            }
+            if let Some((count, err)) = byo_consumer.rejections.report() {
+                log_event(
+                    self.structured_logging,
+                    Level::Error,
+                    &TimestampEvent::RecordsRejected {
+                        source_id: id.to_string(),
+                        count,
+                        error: &err,
+                    },
+                    format_args!(
+                        "Rejected {} consistency records for source {} in the last {} seconds. Most recent error: {}",
+                        count,
+                        byo_consumer.topic,
+                        REJECTION_LOG_INTERVAL.as_secs(),
+                        err
+                    ),
+                );
+            }
+            if let Some(consistency_offset) = byo_consumer.consistency_offset {
+                byo_updates.push((
+                    *id,
+                    ts_updates,
+                    consistency_offset,
+                    byo_consumer.transaction_events,
+                ));
+            }
+        }
+        for id in skipped {
+            self.skip_source(id, "failed to poll the consistency topic".into());
+        }
+        for id in succeeded {
+            self.source_succeeded(id);
+        }
+        for (id, ts_updates, consistency_offset, transaction_events) in byo_updates {
+            if self.byo_sources.get(&id).map_or(true, |cons| cons.durable) {
+                self.byo_persist_timestamp(id, &ts_updates, consistency_offset, transaction_events);
+                if self.shutting_down.get() {
+                    return;
+                }
+            }
+            for (ts, offset) in &ts_updates {
+                log_event(
+                    self.structured_logging,
+                    Level::Debug,
+                    &TimestampEvent::TimestampAdvanced {
+                        source_id: id.to_string(),
+                        timestamp: *ts,
+                        offset: *offset,
+                    },
+                    format_args!(
+                        "Timestamped Source {} up to offset {} at {}",
+                        id, offset, ts
+                    ),
+                );
+            }
+            // Notify coordinator of updates
+            for (ts, offset) in ts_updates {
+                self.send_update(id, ts, offset);
+            }
+        }
+        let complete: Vec<_> = self
+            .byo_sources
+            .iter()
+            .filter(|(_, cons)| cons.is_complete())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in complete {
+            self.retire_source(id);
+        }
+    }
+
+    /// Creates a RT Kafka connector
+    fn create_rt_connector(
+        &self,
+        id: SourceInstanceId,
+        connector: KafkaSourceConnector,
+        last_offset: i64,
+    ) -> Result<RtTimestampConsumer, failure::Error> {
+        let group_id = format!("{}-{}-{}", self.rt_group_id_prefix, connector.topic, id);
+        let k_consumer =
+            create_rt_kafka_consumer(&group_id, &connector, self.client_rack.as_deref())?;
+        let event_consumer = if connector.event_time {
+            Some(create_event_time_kafka_consumer(
+                &group_id,
+                &connector,
+                self.client_rack.as_deref(),
+            )?)
+        } else {
+            None
+        };
+        Ok(RtTimestampConsumer {
+            consumer: k_consumer,
+            event_consumer,
+            group_id,
+            topic: connector.topic.clone(),
+            last_offset,
+            max_increment_size: connector.max_increment_ts_size,
+            caught_up: false,
+            bounded: connector.bounded,
+            end_offset: connector.end_offset,
+            durable: connector.durable,
+            last_update: None,
+            event_time: connector.event_time,
+            recovered_offset: Some(last_offset),
+            recreated: false,
+            high_watermark: None,
+            created_at: SystemTime::now(),
+            consecutive_failures: 0,
+            client_rack: self.client_rack.clone(),
+            paused: self.persisted_paused(id),
+            connector,
+        })
+    }
+
+    /// Creates a BYO Kafka connector. If `consistency_offset` is set, consumption of the
+    /// consistency topic resumes from the record that follows it
+    fn create_byo_connector(
+        &self,
+        id: SourceInstanceId,
+        connector: KafkaSourceConnector,
+        timestamp_topic: String,
+        consistency_offset: Option<i64>,
+    ) -> Result<ByoTimestampConsumer, failure::Error> {
+        let group_id = format!("{}-{}-{}", self.byo_group_id_prefix, timestamp_topic, id);
+        let k_consumer = create_byo_kafka_consumer(
+            &group_id,
+            &connector,
+            self.client_rack.as_deref(),
+            &timestamp_topic,
+            consistency_offset,
+        )?;
+        // The end of a bounded topic is found through the brokers of the topic itself
+        let end_consumer = if connector.bounded && connector.end_offset.is_none() {
+            Some(create_rt_kafka_consumer(
+                &group_id,
+                &connector,
+                self.client_rack.as_deref(),
+            )?)
+        } else {
+            None
+        };
+        Ok(ByoTimestampConsumer {
+            consumer: k_consumer,
+            group_id,
+            topic: connector.topic.clone(),
+            timestamp_topic,
+            compacted: connector.consistency_compacted,
+            rejections: RejectionLog::default(),
+            consistency_offset,
+            max_increment_size: connector.max_increment_ts_size,
+            last_offset: -1,
+            bounded: connector.bounded,
+            end_offset: connector.end_offset,
+            end_consumer,
+            durable: connector.durable,
+            last_update: None,
+            consecutive_failures: 0,
+            client_rack: self.client_rack.clone(),
+            paused: self.persisted_paused(id),
+            last_record: Instant::now(),
+            stalled: false,
+            empty_cycles: 0,
+            recent_records: VecDeque::new(),
+            record_history: self.byo_record_history,
+            added: Instant::now(),
+            created_at: SystemTime::now(),
+            transaction_events: 0,
+            connector,
+        })
+    }
+
+    /// Returns the persisted offset of the last record that was read from the consistency
+    /// topic of that (SourceId,ViewId) pair, along with the number of events counted in
+    /// Debezium transaction records up to it, if any
+    fn byo_recover_consistency_offset(&self, id: SourceInstanceId) -> Option<(i64, i64)> {
+        match self
+            .storage()
+            .prepare_cached(
+                "SELECT offset, transaction_events FROM consistency_offsets \
+                 WHERE sid = ? AND vid = ?",
+            )
+            .expect("Failed to prepare select statement")
+            .query_row(params![SqlVal(&id.sid), SqlVal(&id.vid)], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            }) {
+            Ok(offset) => Some(offset),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(e) => panic!("Failed to parse SQL result: {}", e),
+        }
+    }
+
+    /// Recovers any existing timestamp updates for that (SourceId,ViewId) pair from the underlying
+    /// SQL database. Notifies the coordinator of these updates, minus redundant ones, or only
+    /// of the latest one with fast recovery. Returns the largest recovered offset, or -1 if
+    /// there is none
+    fn recover_source(&mut self, id: SourceInstanceId) -> i64 {
+        let history = self.dump_history(id);
+        let rows = history.len();
+        let ts_updates = if self.fast_recovery {
+            history.last().cloned().into_iter().collect()
+        } else {
+            collapse_history(history)
+        };
+        if ts_updates.len() < rows {
+            debug!(
+                "Collapsed {} timestamp rows for source {} into {}",
+                rows,
+                id,
+                ts_updates.len()
+            );
+        }
+
+        let updates = ts_updates.len();
+        let mut max_offset = -1;
+        let mut last_report = Instant::now();
+        for (recovered, row) in ts_updates.into_iter().enumerate() {
+            if last_report.elapsed() >= RECOVERY_LOG_INTERVAL {
+                info!(
+                    "Recovered {} of {} timestamp rows for source {}",
+                    recovered, updates, id
+                );
+                last_report = Instant::now();
+            }
+            let (ts, offset) = (row.timestamp, row.offset);
+            max_offset = if offset > max_offset {
+                offset
+            } else {
+                max_offset
+            };
+            self.send_update(id, ts, offset);
+        }
+        log_event(
+            self.structured_logging,
+            Level::Info,
+            &TimestampEvent::RecoveryCompleted {
+                source_id: id.to_string(),
+                updates,
+                max_offset,
+            },
+            format_args!(
+                "Recovered {} timestamp updates for Source {}, up to offset {}",
+                updates, id, max_offset
+            ),
+        );
+        if let Some(observer) = &self.observer {
+            observer.on_recovery_complete(id, max_offset);
+        }
+        max_offset
+    }
+
+    /// Returns the raw payloads of the latest consistency records read for a BYO source,
+    /// oldest first. Nothing is retained unless `byo_record_history` is set
+    pub fn recent_consistency_records(&self, id: SourceInstanceId) -> Vec<Vec<u8>> {
+        self.byo_sources.get(&id).map_or_else(Vec::new, |cons| {
+            cons.recent_records.iter().cloned().collect()
+        })
+    }
+
+    /// Writes every persisted timestamp and checkpoint to `writer`, as one JSON object per
+    /// line. Returns the number of rows written
+    pub fn export_timestamps<W: Write>(&self, mut writer: W) -> Result<usize, failure::Error> {
+        let mut rows = vec![];
+        for (table, checkpoint) in &[("timestamps", false), ("checkpoints", true)] {
+            let storage = self.storage();
+            let mut stmt = storage.prepare(&format!(
+                "SELECT sid, vid, timestamp, offset FROM {}",
+                table
+            ))?;
+            let table_rows = stmt.query_map(NO_PARAMS, |row| {
+                let sid: SqlVal<GlobalId> = row.get(0)?;
+                let vid: SqlVal<GlobalId> = row.get(1)?;
+                let timestamp: i64 = row.get(2)?;
+                Ok(ExportedTimestamp {
+                    source_id: SourceInstanceId {
+                        sid: sid.0,
+                        vid: vid.0,
+                    },
+                    timestamp: timestamp as u64,
+                    offset: row.get(3)?,
+                    checkpoint: *checkpoint,
+                })
+            })?;
+            for row in table_rows {
+                rows.push(row?);
+            }
+        }
+        // The checkpoint of a source is written before the rows that follow it
+        rows.sort_by_key(|row| (row.source_id, row.timestamp, !row.checkpoint));
+        for row in &rows {
+            serde_json::to_writer(&mut writer, row)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        Ok(rows.len())
+    }
+
+    /// Imports the timestamps written by `export_timestamps`. Unless `force` is set, the
+    /// import is refused if any of its sources already has persisted timestamps, as it is
+    /// meant to seed a fresh instance. With `force`, the persisted timestamps of these
+    /// sources are replaced. Returns the number of rows imported
+    pub fn import_timestamps<R: BufRead>(
+        &mut self,
+        reader: R,
+        force: bool,
+    ) -> Result<usize, failure::Error> {
+        // Every row is parsed before anything is written, so that a malformed file is
+        // not partially imported
+        let mut rows: Vec<ExportedTimestamp> = vec![];
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(row) => rows.push(row),
+                Err(e) => bail!("invalid timestamp on line {}: {}", i + 1, e),
+            }
+        }
+        let sources: HashSet<_> = rows.iter().map(|row| row.source_id).collect();
+        for id in &sources {
+            if !self.dump_history(*id).is_empty() {
+                if !force {
+                    bail!(
+                        "timestamps of source {} already exist. Force the import to replace them",
+                        id
+                    );
+                }
+                warn!("Replacing the persisted timestamps of source {}", id);
+            }
+        }
+        let storage = self.storage();
+        for id in &sources {
+            for table in &["timestamps", "checkpoints"] {
+                storage
+                    .prepare_cached(&format!("DELETE FROM {} WHERE sid = ? AND vid = ?", table))?
+                    .execute(params![SqlVal(&id.sid), SqlVal(&id.vid)])?;
+            }
+        }
+        for row in &rows {
+            let sql = if row.checkpoint {
+                "INSERT OR REPLACE INTO checkpoints (sid, vid, timestamp, offset) VALUES (?, ?, ?, ?)"
+            } else {
+                "INSERT OR REPLACE INTO timestamps (sid, vid, timestamp, offset) VALUES (?, ?, ?, ?)"
+            };
+            storage.prepare_cached(sql)?.execute(params![
+                SqlVal(&row.source_id.sid),
+                SqlVal(&row.source_id.vid),
+                row.timestamp as i64,
+                row.offset
+            ])?;
+        }
+        drop(storage);
+        // New timestamps must stay ahead of the imported ones
+        if let Some(max_ts) = rows.iter().map(|row| row.timestamp).max() {
+            self.current_timestamp = self.current_timestamp.max(max_ts);
+        }
+        Ok(rows.len())
+    }
+
+    /// Returns the persisted timestamp history of a source, ordered by timestamp, including
+    /// the rows of its checkpoint
+    pub fn dump_history(&self, id: SourceInstanceId) -> Vec<TimestampRow> {
+        let mut rows: Vec<_> = self
+            .storage()
+            .prepare_cached(HISTORY_QUERY)
+            .expect("Failed to execute select statement")
+            .query_and_then(
+                params![SqlVal(&id.sid), SqlVal(&id.vid)],
+                |row| -> Result<_, failure::Error> {
+                    let timestamp: i64 = row.get(0)?;
+                    Ok(TimestampRow {
+                        timestamp: timestamp as u64,
+                        offset: row.get(1)?,
+                    })
+                },
+            )
+            .expect("Failed to parse SQL result")
+            .collect::<Result<_, _>>()
+            .expect("Failed to parse SQL result");
+        // Rows left behind by an interrupted checkpoint are also part of it
+        rows.dedup();
+        rows
+    }
+
+    /// Returns the timestamp assigned to the message at `offset` of a source, which is the
+    /// smallest persisted timestamp whose offset is at least `offset`. Returns `None` if
+    /// that offset has not been timestamped yet
+    pub fn timestamp_for_offset(&self, id: SourceInstanceId, offset: i64) -> Option<u64> {
+        self.storage()
+            .prepare_cached(OFFSET_LOOKUP_QUERY)
+            .expect("Failed to prepare select statement")
+            .query_row(params![SqlVal(&id.sid), SqlVal(&id.vid), offset], |row| {
+                row.get::<_, Option<i64>>(0)
+            })
+            .expect("Failed to execute select statement")
+            .map(|timestamp| timestamp as u64)
+    }
+
+    /// Query real-time sources for the current max offset that has been generated for that source
+    /// Set the new timestamped offset to min(max_offset, last_offset + increment_size): this ensures
+    /// that we never create an overly large batch of messages for the same timestamp (which would
+    /// prevent views from becoming visible in a timely fashion). Also returns the sources
+    /// that have caught up with, or fallen behind, their high watermark
+    fn rt_query_sources(
+        &mut self,
+    ) -> (Vec<(SourceInstanceId, i64)>, Vec<(SourceInstanceId, bool)>) {
+        let max_increment_size = self.max_increment_size;
+        let watermark_timeout = self.watermark_timeout;
+        let structured_logging = self.structured_logging;
+        let query_sources = |sources: &mut [(&SourceInstanceId, &mut RtTimestampConsumer)]| {
+            sources
+                .iter_mut()
+                .map(|(id, cons)| {
+                    (
+                        **id,
+                        cons.query(
+                            **id,
+                            max_increment_size,
+                            watermark_timeout,
+                            structured_logging,
+                        ),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Slow sources only hold up the other sources queried by the same thread
+        let broken = &mut self.broken;
+        let now = Instant::now();
+        let mut sources: Vec<_> = self
+            .rt_sources
+            .iter_mut()
+            .filter(|(id, cons)| !cons.paused && poll_due(broken, **id, now))
+            .collect();
+        let queried = if sources.len() <= 1 {
+            query_sources(&mut sources)
+        } else {
+            let chunk_size = (sources.len() + RT_QUERY_THREADS - 1) / RT_QUERY_THREADS;
+            crossbeam::thread::scope(|scope| {
+                let handles: Vec<_> = sources
+                    .chunks_mut(chunk_size)
+                    .map(|chunk| scope.spawn(move |_| query_sources(chunk)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .flat_map(|handle| handle.join().expect("watermark query thread panicked"))
+                    .collect()
+            })
+            .expect("watermark query thread panicked")
+        };
+
+        let mut result = vec![];
+        let mut caught_up_changes = vec![];
+        for (id, query) in queried {
+            match query {
+                Ok((offset, caught_up)) => {
+                    result.push((id, offset));
+                    if let Some(caught_up) = caught_up {
+                        caught_up_changes.push((id, caught_up));
+                    }
+                }
+                Err(e) => self.skip_source(id, format!("failed to fetch watermarks: {}", e)),
+            }
+        }
+
+        // The persisted history of a recreated topic refers to offsets that no longer
+        // exist, and would otherwise be recovered on restart
+        let recreated: Vec<_> = self
+            .rt_sources
+            .iter_mut()
+            .filter(|(_, cons)| cons.recreated)
+            .map(|(id, cons)| {
+                cons.recreated = false;
+                (*id, cons.durable)
+            })
+            .collect();
+        for (id, durable) in recreated {
+            if durable {
+                self.storage()
+                    .prepare_cached("DELETE FROM timestamps WHERE sid = ? AND vid = ?")
+                    .expect("Failed to prepare delete statement")
+                    .execute(params![SqlVal(&id.sid), SqlVal(&id.vid)])
+                    .expect("Failed to execute delete statement");
+                self.storage()
+                    .prepare_cached("DELETE FROM checkpoints WHERE sid = ? AND vid = ?")
+                    .expect("Failed to prepare delete statement")
+                    .execute(params![SqlVal(&id.sid), SqlVal(&id.vid)])
+                    .expect("Failed to execute delete statement");
+            }
+        }
+        (result, caught_up_changes)
+    }
+
+    /// Assigns timestamps to the new offsets of event time sources, using the Kafka
+    /// timestamp of the message at that offset. Event times go through the same checks as
+    /// generated timestamps: they never run ahead of the current timestamp or past the
+    /// ceiling, and strictly increase per source. Offsets that cannot be assigned a
+    /// timestamp are queried again on the next pass
+    fn rt_event_time_updates(
+        &mut self,
+        watermarks: Vec<(SourceInstanceId, i64)>,
+    ) -> Vec<(SourceInstanceId, u64, i64)> {
+        let mut ts_updates = vec![];
+        for (id, offset) in watermarks {
+            let cons = match self.rt_sources.get_mut(&id) {
+                Some(cons) => cons,
+                None => continue,
+            };
+            if cons
+                .last_update
+                .map_or(false, |(_, last_offset)| last_offset >= offset)
+            {
+                // No new messages, hence no new event time
+                self.source_succeeded(id);
+                continue;
+            }
+            let ts = match cons.event_time_at(offset, self.watermark_timeout) {
+                Some(event_ms) => event_time_timestamp(
+                    event_ms,
+                    self.epoch_ms,
+                    self.current_timestamp,
+                    cons.last_update.map(|(last_ts, _)| last_ts),
+                    self.timestamp_ceiling,
+                ),
+                None => {
+                    cons.last_offset = cons
+                        .last_update
+                        .map_or(cons.recovered_offset.unwrap_or(-1), |(_, o)| o);
+                    self.skip_source(
+                        id,
+                        format!("Failed to read the event time of offset {}", offset),
+                    );
+                    continue;
+                }
+            };
+            match ts {
+                Some(ts) => {
+                    self.current_timestamp = self.current_timestamp.max(ts);
+                    ts_updates.push((id, ts, offset));
+                    self.source_succeeded(id);
+                }
+                None => {
+                    // The ceiling was reached. The offset waits for it to be raised
+                    cons.last_offset = cons
+                        .last_update
+                        .map_or(cons.recovered_offset.unwrap_or(-1), |(_, o)| o);
+                }
+            }
+        }
+        ts_updates
+    }
+
+    /// Persist timestamp updates to the underlying storage when using the
+    /// real-time timestamping logic. With a persist interval, they are only written
+    /// once it elapses
+    fn rt_persist_timestamp(&mut self, ts_updates: &[(SourceInstanceId, u64, i64)]) {
+        if self.persist_interval.is_some() {
+            self.pending_rt.extend_from_slice(ts_updates);
+        } else {
+            self.rt_write_timestamps(ts_updates);
+        }
+    }
+
+    /// Writes timestamp updates of RT sources to the underlying storage
+    fn rt_write_timestamps(&mut self, ts_updates: &[(SourceInstanceId, u64, i64)]) {
+        for (id, ts, offset) in ts_updates {
+            if self.rt_sources.get(id).map_or(false, |cons| !cons.durable) {
+                continue;
+            }
+            if !self.persist(
+                "INSERT OR REPLACE INTO timestamps (sid, vid, timestamp, offset) VALUES (?, ?, ?, ?)",
+                params![SqlVal(&id.sid), SqlVal(&id.vid), *ts as i64, *offset],
+            ) {
+                return;
+            }
+        }
+    }
+
+    /// Persist timestamp updates to the underlying storage when using the BYO
+    /// timestamping logic, along with the offset of the last record that was read
+    /// from the consistency topic and the number of events counted in Debezium
+    /// transaction records up to it. With a persist interval, they are only written
+    /// once it elapses
+    fn byo_persist_timestamp(
+        &mut self,
+        id: SourceInstanceId,
+        ts_updates: &[(u64, i64)],
+        consistency_offset: i64,
+        transaction_events: i64,
+    ) {
+        if self.persist_interval.is_some() {
+            self.pending_byo.push((
+                id,
+                ts_updates.to_vec(),
+                consistency_offset,
+                transaction_events,
+            ));
+        } else {
+            self.byo_write_timestamps(id, ts_updates, consistency_offset, transaction_events);
+        }
+    }
+
+    /// Writes timestamp updates of a BYO source to the underlying storage, followed by
+    /// the offset of the last record that was read from the consistency topic and the
+    /// number of events counted in Debezium transaction records up to it
+    fn byo_write_timestamps(
+        &mut self,
+        id: SourceInstanceId,
+        ts_updates: &[(u64, i64)],
+        consistency_offset: i64,
+        transaction_events: i64,
+    ) {
+        for (ts, offset) in ts_updates {
+            if !self.persist(
+                "INSERT OR REPLACE INTO timestamps (sid, vid, timestamp, offset) VALUES (?, ?, ?, ?)",
+                params![SqlVal(&id.sid), SqlVal(&id.vid), *ts as i64, *offset],
+            ) {
+                return;
+            }
+        }
+        self.persist(
+            "INSERT OR REPLACE INTO consistency_offsets (sid, vid, offset, transaction_events) \
+             VALUES (?, ?, ?, ?)",
+            params![
+                SqlVal(&id.sid),
+                SqlVal(&id.vid),
+                consistency_offset,
+                transaction_events
+            ],
+        );
+    }
+
+    /// Executes a statement that persists timestamping state, retrying until it succeeds.
+    /// Running out of file descriptors is reported once, and the Kafka consumers of paused
+    /// sources are closed to free some. Returns false if the timestamper is shutting down
+    /// before the statement succeeds
+    fn persist(&mut self, sql: &str, params: &[&dyn ToSql]) -> bool {
+        let mut reported = false;
+        loop {
+            let result = lock_storage(&self.storage)
+                .prepare_cached(sql)
+                .and_then(|mut stmt| stmt.execute(params));
+            let err = match result {
+                Ok(_) => return true,
+                Err(err) => err,
+            };
+            if !reported {
+                reported = true;
+                if is_fd_exhaustion(&err) {
+                    error!(
+                        "Failed to persist timestamps: out of file descriptors ({} open). \
+                         Closing the Kafka consumers of paused sources and retrying. \
+                         Raise the file descriptor limit of the process to avoid this",
+                        open_fd_count().map_or("unknown".into(), |count| count.to_string())
+                    );
+                    self.release_paused_consumers();
+                } else {
+                    error!("Failed to persist timestamps: {}. Retrying", err);
+                }
+            }
+            if self.shutdown_requested() {
+                warn!("Shutting down with unpersisted timestamp updates");
+                return false;
+            }
+            std::thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    /// Closes the Kafka consumers of paused sources, to release their file descriptors.
+    /// They are created again when the sources resume
+    fn release_paused_consumers(&mut self) {
+        let rt_paused: Vec<_> = self
+            .rt_sources
+            .iter()
+            .filter(|(_, cons)| cons.paused)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in rt_paused {
+            if let Some(cons) = self.rt_sources.remove(&id) {
+                info!("Closing the Kafka consumer of paused Source {}", id);
+                self.released.insert(
+                    id,
+                    ReleasedSource::Rt {
+                        connector: cons.connector,
+                        last_offset: cons.last_offset,
+                        last_update: cons.last_update,
+                    },
+                );
+            }
+        }
+        let byo_paused: Vec<_> = self
+            .byo_sources
+            .iter()
+            .filter(|(_, cons)| cons.paused)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in byo_paused {
+            if let Some(cons) = self.byo_sources.remove(&id) {
+                info!("Closing the Kafka consumer of paused Source {}", id);
+                self.released.insert(
+                    id,
+                    ReleasedSource::Byo {
+                        connector: cons.connector,
+                        timestamp_topic: cons.timestamp_topic,
+                        consistency_offset: cons.consistency_offset,
+                        transaction_events: cons.transaction_events,
+                        last_offset: cons.last_offset,
+                        last_update: cons.last_update,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Creates the Kafka consumer of a source again, if it was closed while the source
+    /// was paused
+    fn restore_released_consumer(&mut self, id: SourceInstanceId) {
+        match self.released.remove(&id) {
+            Some(ReleasedSource::Rt {
+                connector,
+                last_offset,
+                last_update,
+            }) => match self.create_rt_connector(id, connector.clone(), last_offset) {
+                Ok(mut cons) => {
+                    cons.last_update = last_update;
+                    cons.recovered_offset = None;
+                    self.rt_sources.insert(id, cons);
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to recreate the Kafka consumer of Source {}: {}. It stays paused",
+                        id, e
+                    );
+                    self.released.insert(
+                        id,
+                        ReleasedSource::Rt {
+                            connector,
+                            last_offset,
+                            last_update,
+                        },
+                    );
+                }
+            },
+            Some(ReleasedSource::Byo {
+                connector,
+                timestamp_topic,
+                consistency_offset,
+                transaction_events,
+                last_offset,
+                last_update,
+            }) => {
+                match self.create_byo_connector(
+                    id,
+                    connector.clone(),
+                    timestamp_topic.clone(),
+                    consistency_offset,
+                ) {
+                    Ok(mut cons) => {
+                        cons.transaction_events = transaction_events;
+                        cons.last_offset = last_offset;
+                        cons.last_update = last_update;
+                        self.byo_sources.insert(id, cons);
+                    }
+                    Err(e) => {
+                        error!(
+                            "Failed to recreate the Kafka consumer of Source {}: {}. It stays paused",
+                            id, e
+                        );
+                        self.released.insert(
+                            id,
+                            ReleasedSource::Byo {
+                                connector,
+                                timestamp_topic,
+                                consistency_offset,
+                                transaction_events,
+                                last_offset,
+                                last_update,
+                            },
+                        );
+                    }
+                }
+            }
+            None => (),
+        }
+    }
+
+    /// Notify coordinator of a batch of timestamp updates, all with the same timestamp
+    /// Used in real-time timestamping logic, where a set of sources get assigned the same
+    /// timestamp
+    fn rt_notify_coordinator(&self, ts_updates: Vec<(SourceInstanceId, i64)>) {
+        if let Some(audit) = &self.audit {
+            for (id, offset) in &ts_updates {
+                audit.mirror(*id, self.current_timestamp, *offset);
+            }
+        }
+        self.sink.advance_batch(self.current_timestamp, ts_updates);
+    }
+
+    /// Sends a timestamp update of a single source to the coordinator
+    fn send_update(&self, id: SourceInstanceId, ts: u64, offset: i64) {
+        if let Some(audit) = &self.audit {
+            audit.mirror(id, ts, offset);
+        }
+        self.sink.advance(SourceTimestampUpdate {
+            source_id: id,
+            timestamp: ts,
+            offset,
+        });
+    }
+
+    /// Generates a timestamp that is guaranteed to be monotonically increasing.
+    /// This may require multiple calls to the underlying now() system method, which is not
+    /// guaranteed to increase monotonically. When coalescing, the current timestamp is
+    /// reused instead if the clock has not moved past it
+    fn rt_generate_next_timestamp(&mut self) {
+        if self.current_timestamp >= self.timestamp_ceiling {
+            return;
+        }
+        let mut new_ts = 0;
+        while new_ts <= self.current_timestamp {
+            let start = (self.clock)();
+            new_ts = (start
+                .duration_since(UNIX_EPOCH)
+                .expect("Time went backwards")
+                .as_millis() as u64)
+                // A clock that moved back before the epoch is waited for, like one that
+                // moved back before the current timestamp
+                .saturating_sub(self.epoch_ms)
+                .min(self.timestamp_ceiling);
+            if self.coalesce_timestamps && new_ts <= self.current_timestamp {
+                return;
+            }
+        }
+        assert!(new_ts > self.current_timestamp);
+        self.current_timestamp = new_ts;
+        if !self.ceiling_approached
+            && new_ts.saturating_add(TIMESTAMP_CEILING_WARNING_MARGIN) >= self.timestamp_ceiling
+        {
+            self.ceiling_approached = true;
+            warn!(
+                "Timestamp {} is approaching the timestamp ceiling {}",
+                new_ts, self.timestamp_ceiling
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Receiver, Sender};
+
+    use dataflow_types::TimestampUnit;
+    use expr::GlobalId;
+
+    use super::*;
+
+    /// Returns a timestamper backed by an in-memory catalog, along with the ends of its
+    /// channel that the coordinator would hold
+    fn in_memory_timestamper() -> (
+        Timestamper,
+        Sender<TimestampMessage>,
+        Receiver<TimestampMessage>,
+    ) {
+        let storage = catalog::sql::Connection::open(None).unwrap();
+        timestamper_with_storage(Arc::new(Mutex::new(storage)))
+    }
+
+    fn timestamper_with_storage(
+        storage: Arc<Mutex<catalog::sql::Connection>>,
+    ) -> (
+        Timestamper,
+        Sender<TimestampMessage>,
+        Receiver<TimestampMessage>,
+    ) {
+        timestamper_with_config(&TimestampConfig::default(), storage)
+    }
+
+    fn timestamper_with_config(
+        config: &TimestampConfig,
+        storage: Arc<Mutex<catalog::sql::Connection>>,
+    ) -> (
+        Timestamper,
+        Sender<TimestampMessage>,
+        Receiver<TimestampMessage>,
+    ) {
+        let (coord_tx, coord_rx) = mpsc::channel();
+        let (ts_tx, ts_rx) = mpsc::channel();
+        let timestamper = Timestamper::new(
+            config,
+            storage,
+            TimestampChannel {
+                sender: coord_tx,
+                receiver: ts_rx,
+            },
+        )
+        .unwrap();
+        (timestamper, ts_tx, coord_rx)
+    }
+
+    fn kafka_connector() -> KafkaSourceConnector {
+        KafkaSourceConnector {
+            url: "kafka://localhost:9092".parse().unwrap(),
+            topic: "topic".into(),
+            ssl_certificate_file: None,
+            consistency_compacted: false,
+            max_increment_ts_size: None,
+            bounded: false,
+            end_offset: None,
+            consistency_read_committed: true,
+            durable: true,
+            consistency_timestamp_unit: TimestampUnit::Millis,
+            consistency_format: ConsistencyFormat::Csv(CsvConsistencyColumns::default()),
+            event_time: false,
+            consistency_url: None,
+            consistency_ssl_certificate_file: None,
+            consistency_source_name_from_key: false,
+            consistency_compression: ConsistencyCompression::None,
+            consistency_timestamp_correction: 0,
+            consistency_format_header: None,
+            config_options: HashMap::new(),
+        }
+    }
+
+    fn source_id() -> SourceInstanceId {
+        SourceInstanceId {
+            sid: GlobalId::User(1),
+            vid: GlobalId::User(2),
+        }
+    }
+
+    #[test]
+    fn recover_debezium_transaction_events() {
+        let storage = Arc::new(Mutex::new(catalog::sql::Connection::open(None).unwrap()));
+        let id = source_id();
+        let (mut timestamper, _ts_tx, _coord_rx) = timestamper_with_storage(storage.clone());
+        // Transactions of 3 and 2 events were timestamped before the restart
+        timestamper.byo_persist_timestamp(id, &[(10, 2), (20, 4)], 7, 5);
+        drop(timestamper);
+
+        let (mut timestamper, ts_tx, _coord_rx) = timestamper_with_storage(storage);
+        ts_tx
+            .send(TimestampMessage::Add(
+                id,
+                KafkaSourceConnector {
+                    consistency_format: ConsistencyFormat::DebeziumTransaction,
+                    ..kafka_connector()
+                },
+                Consistency::BringYourOwn("consistency".into()),
+            ))
+            .unwrap();
+        timestamper.update_sources();
+        let mut cons = timestamper.byo_sources.remove(&id).unwrap();
+        assert_eq!(cons.transaction_events, 5);
+
+        // The next transaction covers the events that follow the recovered ones, at a
+        // later timestamp than theirs
+        let message = ConsistencyMessage {
+            key: None,
+            payload: br#"{"status": "END", "ts_ms": 15,
+                "data_collections": [{"data_collection": "topic", "event_count": 2}]}"#
+                .to_vec(),
+            offset: 8,
+            timestamp: None,
+            format: None,
+        };
+        assert_eq!(
+            byo_extract_ts_update(&mut cons, vec![message]),
+            vec![(21, 6)]
+        );
+    }
+
+    #[test]
+    fn recover_in_memory() {
+        let (mut timestamper, _ts_tx, coord_rx) = in_memory_timestamper();
+        let id = source_id();
+        timestamper.byo_persist_timestamp(id, &[(9, 100), (10, 5)], 3, 0);
+
+        assert_eq!(
+            timestamper.dump_history(id),
+            vec![
+                TimestampRow {
+                    timestamp: 9,
+                    offset: 100
+                },
+                TimestampRow {
+                    timestamp: 10,
+                    offset: 5
+                },
+            ]
+        );
+        assert_eq!(timestamper.byo_recover_consistency_offset(id), Some((3, 0)));
+        assert_eq!(timestamper.recover_source(id), 100);
+        let recovered: Vec<_> = coord_rx
+            .try_iter()
+            .map(|update| match update {
+                TimestampMessage::Update(_, ts, offset) => (ts, offset),
+                update => panic!("unexpected message {:?}", update),
+            })
+            .collect();
+        assert_eq!(recovered, vec![(9, 100), (10, 5)]);
+    }
+
+    #[test]
+    fn recover_after_restart() {
+        let storage = Arc::new(Mutex::new(catalog::sql::Connection::open(None).unwrap()));
+        let id = source_id();
+
+        let (mut timestamper, _ts_tx, _coord_rx) = timestamper_with_storage(storage.clone());
+        let mut written = vec![];
+        for offset in &[3, 8, 8, 15] {
+            timestamper.rt_generate_next_timestamp();
+            let ts = timestamper.current_timestamp;
+            timestamper.rt_persist_timestamp(&[(id, ts, *offset)]);
+            written.push((ts, *offset));
+        }
+        let last_ts = timestamper.current_timestamp;
+        drop(timestamper);
+
+        let (mut timestamper, _ts_tx, coord_rx) = timestamper_with_storage(storage);
+        assert_eq!(timestamper.current_timestamp, last_ts);
+        assert_eq!(timestamper.recover_source(id), 15);
+        let recovered: Vec<_> = coord_rx
+            .try_iter()
+            .map(|update| match update {
+                TimestampMessage::Update(_, ts, offset) => (ts, offset),
+                update => panic!("unexpected message {:?}", update),
+            })
+            .collect();
+        // The second update at offset 8 only advanced the frontier
+        assert_eq!(recovered, vec![written[0], written[1], written[3]]);
+
+        timestamper.rt_generate_next_timestamp();
+        assert!(timestamper.current_timestamp > last_ts);
+    }
+
+    #[test]
+    fn reject_changed_epoch() {
+        let storage = Arc::new(Mutex::new(catalog::sql::Connection::open(None).unwrap()));
+        let (timestamper, _ts_tx, _coord_rx) = timestamper_with_storage(storage.clone());
+        drop(timestamper);
+
+        let (coord_tx, _coord_rx) = mpsc::channel();
+        let (_ts_tx, ts_rx) = mpsc::channel();
+        let timestamper = Timestamper::new(
+            &TimestampConfig::builder()
+                .epoch(Duration::from_secs(1_577_836_800))
+                .build(),
+            storage,
+            TimestampChannel {
+                sender: coord_tx,
+                receiver: ts_rx,
+            },
+        );
+        assert!(timestamper.is_err());
+    }
+
+    #[test]
+    fn lookup_timestamp_for_offset() {
+        let (mut timestamper, _ts_tx, _coord_rx) = in_memory_timestamper();
+        let id = source_id();
+        timestamper.rt_persist_timestamp(&[(id, 10, 3), (id, 20, 8), (id, 30, 15)]);
+
+        assert_eq!(timestamper.timestamp_for_offset(id, 0), Some(10));
+        assert_eq!(timestamper.timestamp_for_offset(id, 3), Some(10));
+        assert_eq!(timestamper.timestamp_for_offset(id, 4), Some(20));
+        assert_eq!(timestamper.timestamp_for_offset(id, 15), Some(30));
+        assert_eq!(timestamper.timestamp_for_offset(id, 16), None);
+
+        // A checkpoint leaves the timestamps of offsets unchanged
+        timestamper.checkpoint_source(id);
+        timestamper.rt_persist_timestamp(&[(id, 40, 20)]);
+        assert_eq!(timestamper.timestamp_for_offset(id, 0), Some(10));
+        assert_eq!(timestamper.timestamp_for_offset(id, 4), Some(20));
+        assert_eq!(timestamper.timestamp_for_offset(id, 15), Some(30));
+        assert_eq!(timestamper.timestamp_for_offset(id, 16), Some(40));
+        assert_eq!(timestamper.timestamp_for_offset(id, 21), None);
+    }
+
+    #[test]
+    fn index_timestamp_queries() {
+        let (mut timestamper, _ts_tx, _coord_rx) = in_memory_timestamper();
+        let id = source_id();
+        timestamper.rt_persist_timestamp(&[(id, 10, 3), (id, 20, 8)]);
+        let storage = timestamper.storage();
+        let plan = |query: &str, params: &[&dyn ToSql]| -> String {
+            storage
+                .prepare(&format!("EXPLAIN QUERY PLAN {}", query))
+                .unwrap()
+                .query_map(params, |row| row.get::<_, String>(3))
+                .unwrap()
+                .map(|detail| detail.unwrap())
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        // The history is read in order through the indexes backing the primary keys
+        let recovery = plan(HISTORY_QUERY, params![SqlVal(&id.sid), SqlVal(&id.vid)]);
+        for index in &[
+            "sqlite_autoindex_timestamps_1",
+            "sqlite_autoindex_checkpoints_1",
+        ] {
+            assert!(
+                recovery.contains(&format!("USING INDEX {}", index)),
+                "{}",
+                recovery
+            );
+        }
+        assert!(!recovery.contains("TEMP B-TREE"), "{}", recovery);
+        // Offsets are looked up through the offset indexes
+        let lookup = plan(
+            OFFSET_LOOKUP_QUERY,
+            params![SqlVal(&id.sid), SqlVal(&id.vid), 5],
+        );
+        for index in &["timestamps_offset", "checkpoints_offset"] {
+            assert!(
+                lookup.contains(&format!("USING INDEX {}", index)),
+                "{}",
+                lookup
+            );
+        }
+    }
+
+    #[test]
+    fn promote_standby() {
+        let storage = Arc::new(Mutex::new(catalog::sql::Connection::open(None).unwrap()));
+        let id = source_id();
+        let (mut active, _ts_tx, _coord_rx) = timestamper_with_storage(storage.clone());
+        active.rt_persist_timestamp(&[(id, 10, 3), (id, 20, 8)]);
+
+        let (mut standby, _standby_tx, standby_rx) =
+            timestamper_with_config(&TimestampConfig::builder().standby(true).build(), storage);
+        standby.force_advance();
+        standby.resync();
+        assert!(standby_rx.try_recv().is_err());
+
+        // The active timestamper keeps going until it fails over
+        active.rt_persist_timestamp(&[(id, 30, 15)]);
+        drop(active);
+
+        standby.promote();
+        assert_eq!(standby.current_timestamp, 30);
+        standby.rt_generate_next_timestamp();
+        assert!(standby.current_timestamp > 30);
+    }
+
+    #[test]
+    fn drop_removes_all_state() {
+        let (mut timestamper, ts_tx, _coord_rx) = in_memory_timestamper();
+        let id = source_id();
+        ts_tx
+            .send(TimestampMessage::Add(
+                id,
+                kafka_connector(),
+                Consistency::RealTime,
+            ))
+            .unwrap();
+        let before_add = SystemTime::now();
+        timestamper.update_sources();
+        assert!(timestamper.rt_sources.contains_key(&id));
+        let created_times = timestamper.source_created_times();
+        assert_eq!(created_times.len(), 1);
+        assert!(created_times[0].1 >= before_add);
+        for offset in &[3, 8, 15] {
+            timestamper.rt_generate_next_timestamp();
+            let ts = timestamper.current_timestamp;
+            timestamper.rt_persist_timestamp(&[(id, ts, *offset)]);
+        }
+        timestamper.byo_persist_timestamp(id, &[], 4, 0);
+        timestamper.set_paused(id, true);
+        let last_ts = timestamper.current_timestamp;
+
+        ts_tx.send(TimestampMessage::DropInstance(id)).unwrap();
+        timestamper.update_sources();
+        assert!(!timestamper.rt_sources.contains_key(&id));
+        assert!(!timestamper.byo_sources.contains_key(&id));
+        assert!(timestamper.dump_history(id).is_empty());
+        assert_eq!(timestamper.byo_recover_consistency_offset(id), None);
+        assert!(!timestamper.persisted_paused(id));
+
+        // A re-added source starts from scratch, but timestamps keep increasing
+        ts_tx
+            .send(TimestampMessage::Add(
+                id,
+                kafka_connector(),
+                Consistency::RealTime,
+            ))
+            .unwrap();
+        timestamper.update_sources();
+        let cons = &timestamper.rt_sources[&id];
+        assert_eq!(cons.last_offset, -1);
+        assert!(!cons.paused);
+        timestamper.rt_generate_next_timestamp();
+        assert!(timestamper.current_timestamp > last_ts);
+    }
+
+    #[derive(Clone, Default)]
+    struct CapturingSink(Arc<Mutex<Vec<SourceTimestampUpdate>>>);
+
+    impl TimestampSink for CapturingSink {
+        fn advance(&self, update: SourceTimestampUpdate) {
+            self.0.lock().unwrap().push(update);
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingObserver(Arc<Mutex<Vec<String>>>);
+
+    impl TimestamperObserver for RecordingObserver {
+        fn on_recovery_complete(&self, id: SourceInstanceId, max_offset: i64) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("recovered {} up to {}", id, max_offset));
+        }
+
+        fn on_source_broken(&self, id: SourceInstanceId, reason: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("broken {}: {}", id, reason));
+        }
+
+        fn on_source_healthy(&self, id: SourceInstanceId) {
+            self.0.lock().unwrap().push(format!("healthy {}", id));
+        }
+    }
+
+    #[test]
+    fn notify_observer() {
+        let (timestamper, _ts_tx, _coord_rx) = in_memory_timestamper();
+        let observer = RecordingObserver::default();
+        let mut timestamper = timestamper.with_observer(Box::new(observer.clone()));
+        let id = source_id();
+        timestamper.rt_persist_timestamp(&[(id, 10, 3), (id, 20, 8)]);
+        timestamper.recover_source(id);
+        for _ in 0..MAX_SKIPPED_PASSES {
+            timestamper.skip_source(id, "unreachable".into());
+        }
+        timestamper.source_succeeded(id);
+
+        assert_eq!(
+            *observer.0.lock().unwrap(),
+            vec![
+                format!("recovered {} up to 8", id),
+                format!("broken {}: unreachable", id),
+                format!("healthy {}", id),
+            ]
+        );
+    }
+
+    #[test]
+    fn send_updates_to_sink() {
+        let (timestamper, _ts_tx, coord_rx) = in_memory_timestamper();
+        let sink = CapturingSink::default();
+        let mut timestamper = timestamper.with_sink(Box::new(sink.clone()));
+        let id = source_id();
+        timestamper.rt_persist_timestamp(&[(id, 10, 3), (id, 20, 8)]);
+        timestamper.recover_source(id);
+        timestamper.current_timestamp = 30;
+        timestamper.rt_notify_coordinator(vec![(id, 15)]);
+
+        let update = |timestamp, offset| SourceTimestampUpdate {
+            source_id: id,
+            timestamp,
+            offset,
+        };
+        assert_eq!(
+            *sink.0.lock().unwrap(),
+            vec![update(10, 3), update(20, 8), update(30, 15)]
+        );
+        assert!(coord_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn stop_at_timestamp_ceiling() {
+        let (mut timestamper, _ts_tx, _coord_rx) = in_memory_timestamper();
+        timestamper.timestamp_ceiling = 1000;
+        timestamper.rt_generate_next_timestamp();
+        assert_eq!(timestamper.current_timestamp, 1000);
+        assert!(timestamper.ceiling_approached);
+        timestamper.rt_generate_next_timestamp();
+        assert_eq!(timestamper.current_timestamp, 1000);
+    }
+
+    #[test]
+    fn recover_latest_timestamp_only() {
+        let (mut timestamper, _ts_tx, coord_rx) = in_memory_timestamper();
+        timestamper.fast_recovery = true;
+        let id = source_id();
+        timestamper.rt_persist_timestamp(&[(id, 10, 3), (id, 20, 8), (id, 30, 15)]);
+
+        assert_eq!(timestamper.recover_source(id), 15);
+        let recovered: Vec<_> = coord_rx
+            .try_iter()
+            .map(|update| match update {
+                TimestampMessage::Update(_, ts, offset) => (ts, offset),
+                update => panic!("unexpected message {:?}", update),
+            })
+            .collect();
+        assert_eq!(recovered, vec![(30, 15)]);
+    }
+
+    #[test]
+    fn checkpoint_timestamp_history() {
+        let (mut timestamper, _ts_tx, coord_rx) = in_memory_timestamper();
+        let id = source_id();
+        timestamper.rt_persist_timestamp(&[(id, 10, 3), (id, 20, 8), (id, 25, 8), (id, 30, 15)]);
+        timestamper.checkpoint_source(id);
+
+        let remaining: i64 = timestamper
+            .storage()
+            .prepare("SELECT count(*) FROM timestamps")
+            .unwrap()
+            .query_row(NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(remaining, 0);
+        // Only the row that does not advance the offset is dropped
+        assert_eq!(
+            timestamper.dump_history(id),
+            vec![
+                TimestampRow {
+                    timestamp: 10,
+                    offset: 3
+                },
+                TimestampRow {
+                    timestamp: 20,
+                    offset: 8
+                },
+                TimestampRow {
+                    timestamp: 30,
+                    offset: 15
+                }
+            ]
+        );
+
+        // Timestamps persisted after the checkpoint are recovered along with it
+        timestamper.rt_persist_timestamp(&[(id, 40, 20)]);
+        assert_eq!(timestamper.recover_source(id), 20);
+        let recovered: Vec<_> = coord_rx
+            .try_iter()
+            .map(|update| match update {
+                TimestampMessage::Update(_, ts, offset) => (ts, offset),
+                update => panic!("unexpected message {:?}", update),
+            })
+            .collect();
+        assert_eq!(recovered, vec![(10, 3), (20, 8), (30, 15), (40, 20)]);
+    }
+
+    #[test]
+    fn truncate_timestamp_history() {
+        let (mut timestamper, _ts_tx, _coord_rx) = in_memory_timestamper();
+        let id = source_id();
+        timestamper.rt_persist_timestamp(&[(id, 10, 3), (id, 20, 8)]);
+        timestamper.checkpoint_source(id);
+        timestamper.rt_persist_timestamp(&[(id, 30, 15), (id, 40, 20)]);
+
+        assert_eq!(timestamper.truncate_history(id, 30), 8);
+        assert_eq!(
+            timestamper.dump_history(id),
+            vec![
+                TimestampRow {
+                    timestamp: 10,
+                    offset: 3
+                },
+                TimestampRow {
+                    timestamp: 20,
+                    offset: 8
+                }
+            ]
+        );
+
+        // Truncating within the checkpoint removes its later rows as well
+        assert_eq!(timestamper.truncate_history(id, 15), 3);
+        assert_eq!(
+            timestamper.dump_history(id),
+            vec![TimestampRow {
+                timestamp: 10,
+                offset: 3
+            }]
+        );
+    }
+
+    #[test]
+    fn delay_timestamp_persistence() {
+        let (mut timestamper, _ts_tx, _coord_rx) = in_memory_timestamper();
+        timestamper.persist_interval = Some(Duration::from_secs(3600));
+        let id = source_id();
+        timestamper.rt_persist_timestamp(&[(id, 10, 3), (id, 20, 8)]);
+        assert_eq!(timestamper.dump_history(id), vec![]);
+
+        // Nothing is written before the interval elapses
+        timestamper.maybe_flush_pending_timestamps();
+        assert_eq!(timestamper.dump_history(id), vec![]);
+
+        timestamper.flush_pending_timestamps();
+        assert_eq!(
+            timestamper.dump_history(id),
+            vec![
+                TimestampRow {
+                    timestamp: 10,
+                    offset: 3
+                },
+                TimestampRow {
+                    timestamp: 20,
+                    offset: 8
+                }
+            ]
+        );
+    }
+
+    /// Returns a clock that moves forward overall, but that regularly returns the same time
+    /// as, or an earlier time than, its previous reading
+    fn unsteady_clock(start_ms: u64) -> Box<dyn FnMut() -> SystemTime + Send> {
+        let mut readings = 0;
+        Box::new(move || {
+            let jitter = [0, 3, 1, 0][readings as usize % 4];
+            let ms = start_ms + readings / 4 + jitter;
+            readings += 1;
+            UNIX_EPOCH + Duration::from_millis(ms)
+        })
+    }
+
+    #[test]
+    fn stream_updates() {
+        let storage = Arc::new(Mutex::new(catalog::sql::Connection::open(None).unwrap()));
+        let (coord_tx, coord_rx) = mpsc::channel();
+        let (_ts_tx, ts_rx) = mpsc::channel();
+        let (mut timestamper, updates) = Timestamper::new_with_stream(
+            &TimestampConfig::default(),
+            storage,
+            TimestampChannel {
+                sender: coord_tx,
+                receiver: ts_rx,
+            },
+        )
+        .unwrap();
+        let id = source_id();
+        timestamper.current_timestamp = 10;
+        timestamper.rt_notify_coordinator(vec![(id, 3)]);
+        timestamper.current_timestamp = 20;
+        timestamper.rt_notify_coordinator(vec![(id, 8)]);
+        drop(timestamper);
+
+        let updates: Vec<_> = futures::executor::block_on_stream(updates)
+            .map(|update| (update.timestamp, update.offset))
+            .collect();
+        assert_eq!(updates, vec![(10, 3), (20, 8)]);
+        assert!(coord_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn timestamps_never_regress() {
+        let storage = Arc::new(Mutex::new(catalog::sql::Connection::open(None).unwrap()));
+        let (timestamper, _ts_tx, _coord_rx) = timestamper_with_storage(storage.clone());
+        let mut timestamper = timestamper.with_clock(unsteady_clock(1_000));
+        let mut last_ts = timestamper.current_timestamp;
+        for _ in 0..10_000 {
+            timestamper.rt_generate_next_timestamp();
+            assert!(timestamper.current_timestamp > last_ts);
+            last_ts = timestamper.current_timestamp;
+        }
+        timestamper.rt_persist_timestamp(&[(source_id(), last_ts, 0)]);
+        drop(timestamper);
+
+        // After a restart, timestamps resume from the recovered ones, even though the
+        // clock lags behind them
+        let (timestamper, _ts_tx, _coord_rx) = timestamper_with_storage(storage);
+        let mut timestamper = timestamper.with_clock(unsteady_clock(0));
+        assert_eq!(timestamper.current_timestamp, last_ts);
+        for _ in 0..10_000 {
+            timestamper.rt_generate_next_timestamp();
+            assert!(timestamper.current_timestamp > last_ts);
+            last_ts = timestamper.current_timestamp;
+        }
+    }
+
+    #[test]
+    fn retain_latest_records() {
+        let messages = |payloads: &[&str]| -> Vec<ConsistencyMessage> {
+            payloads
+                .iter()
+                .enumerate()
+                .map(|(offset, payload)| ConsistencyMessage {
+                    key: None,
+                    payload: payload.as_bytes().to_vec(),
+                    offset: offset as i64,
+                    timestamp: None,
+                    format: None,
+                })
+                .collect()
+        };
+        let mut records = VecDeque::new();
+        retain_recent_records(&mut records, &messages(&["a", "b"]), 3);
+        retain_recent_records(&mut records, &messages(&["c", "d"]), 3);
+        assert_eq!(records, vec![b"b".to_vec(), b"c".to_vec(), b"d".to_vec()]);
+        retain_recent_records(&mut records, &messages(&["e", "f", "g", "h"]), 3);
+        assert_eq!(records, vec![b"f".to_vec(), b"g".to_vec(), b"h".to_vec()]);
+
+        let mut records = VecDeque::new();
+        retain_recent_records(&mut records, &messages(&["a"]), 0);
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn correct_skewed_timestamps() {
+        assert_eq!(correct_timestamp(1_000, 250).unwrap(), 1_250);
+        assert_eq!(correct_timestamp(1_000, -250).unwrap(), 750);
+        assert_eq!(correct_timestamp(1_000, 0).unwrap(), 1_000);
+        assert_eq!(
+            correct_timestamp(1_000, i64::min_value())
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "timestamp 1000 is out of range once corrected by {} milliseconds",
+                i64::min_value()
+            )
+        );
+        assert!(correct_timestamp(u64::max_value(), 1).is_err());
+    }
+
+    #[test]
+    fn export_and_import_timestamps() {
+        let (mut source, _ts_tx, _coord_rx) = in_memory_timestamper();
+        let id = source_id();
+        source.rt_persist_timestamp(&[(id, 10, 3), (id, 20, 8)]);
+        source.checkpoint_source(id);
+        source.rt_persist_timestamp(&[(id, 30, 15)]);
+        let mut exported = vec![];
+        assert_eq!(source.export_timestamps(&mut exported).unwrap(), 3);
+
+        let (mut target, _ts_tx, _coord_rx) = in_memory_timestamper();
+        assert_eq!(target.import_timestamps(&exported[..], false).unwrap(), 3);
+        assert_eq!(target.dump_history(id), source.dump_history(id));
+        assert_eq!(target.current_timestamp, 30);
+
+        // Existing timestamps are only replaced when forced
+        assert!(target.import_timestamps(&exported[..], false).is_err());
+        assert_eq!(target.import_timestamps(&exported[..], true).unwrap(), 3);
+        assert_eq!(target.dump_history(id), source.dump_history(id));
+    }
+
+    #[test]
+    fn reject_non_positive_max_size() {
+        assert!(TimestampConfig::builder()
+            .max_size(1)
+            .build()
+            .validate()
+            .is_ok());
+        for max_size in &[0, -1] {
+            let config = TimestampConfig::builder().max_size(*max_size).build();
+            assert_eq!(
+                config.validate().unwrap_err().to_string(),
+                format!("max_size must be positive, got {}", max_size)
+            );
+        }
+    }
+
+    #[test]
+    fn reject_future_epoch() {
+        assert!(TimestampConfig::builder()
+            .epoch(Duration::from_secs(1_577_836_800))
+            .build()
+            .validate()
+            .is_ok());
+        let future =
+            SystemTime::now().duration_since(UNIX_EPOCH).unwrap() + Duration::from_secs(3600);
+        assert!(TimestampConfig::builder()
+            .epoch(future)
+            .build()
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn coalesce_rt_timestamps() {
+        let (mut timestamper, _ts_tx, _coord_rx) = in_memory_timestamper();
+        timestamper.coalesce_timestamps = true;
+        // Far ahead of the clock, but below the timestamp ceiling
+        timestamper.current_timestamp = u64::max_value() / 2;
+        timestamper.rt_generate_next_timestamp();
+        assert_eq!(timestamper.current_timestamp, u64::max_value() / 2);
+
+        // Coalesced updates of the same timestamp replace each other
+        let id = source_id();
+        timestamper.rt_persist_timestamp(&[(id, 5, 3)]);
+        timestamper.rt_persist_timestamp(&[(id, 5, 8)]);
+        assert_eq!(
+            timestamper.dump_history(id),
+            vec![TimestampRow {
+                timestamp: 5,
+                offset: 8
+            }]
+        );
+    }
+
+    #[test]
+    fn bound_rt_increments() {
+        let mut offset = 0;
+        let mut offsets = vec![];
+        for _ in 0..5 {
+            offset = rt_next_offset(offset, 25, 10);
+            offsets.push(offset);
+        }
+        assert_eq!(offsets, vec![10, 20, 25, 25, 25]);
+
+        assert_eq!(rt_next_offset(20, 30, 10), 30);
+        assert_eq!(rt_next_offset(20, 31, 10), 30);
+        // A high watermark behind the last offset never moves the offset backwards
+        assert_eq!(rt_next_offset(20, 5, 10), 20);
+        assert_eq!(rt_next_offset(20, -1, 10), 20);
+    }
+
+    #[test]
+    fn bound_event_time_timestamps() {
+        assert_eq!(event_time_timestamp(150, 100, 80, None, 1000), Some(50));
+        // Event times are never ahead of the current timestamp
+        assert_eq!(event_time_timestamp(500, 100, 80, None, 1000), Some(80));
+        // An event time before the epoch starts at zero
+        assert_eq!(event_time_timestamp(50, 100, 80, None, 1000), Some(0));
+        // Timestamps of a source strictly increase
+        assert_eq!(event_time_timestamp(150, 100, 80, Some(60), 1000), Some(61));
+        assert_eq!(event_time_timestamp(150, 100, 80, Some(40), 1000), Some(50));
+        // Timestamps never pass the ceiling
+        assert_eq!(event_time_timestamp(150, 100, 80, Some(80), 80), None);
+        assert_eq!(
+            event_time_timestamp(150, 100, 80, Some(u64::max_value()), 80),
+            None
+        );
+    }
+
+    #[test]
+    fn collapse_redundant_history() {
+        let rows = |rows: &[(u64, i64)]| -> Vec<TimestampRow> {
+            rows.iter()
+                .map(|(timestamp, offset)| TimestampRow {
+                    timestamp: *timestamp,
+                    offset: *offset,
+                })
+                .collect()
+        };
+        assert_eq!(collapse_history(vec![]), vec![]);
+        assert_eq!(
+            collapse_history(rows(&[(1, 0), (2, 0), (3, 5), (4, 5), (5, 5), (6, 7)])),
+            rows(&[(1, 0), (3, 5), (6, 7)])
+        );
+        assert_eq!(
+            collapse_history(rows(&[(1, 0), (2, 5), (3, 5), (4, 5)])),
+            rows(&[(1, 0), (2, 5), (4, 5)])
+        );
+    }
+
+    #[test]
+    fn break_failing_source() {
+        let (mut timestamper, ts_tx, _coord_rx) = in_memory_timestamper();
+        let id = source_id();
+        for _ in 1..MAX_SKIPPED_PASSES {
+            timestamper.skip_source(id, "unreachable".into());
+        }
+        assert!(timestamper.broken_sources().is_empty());
+        timestamper.skip_source(id, "unreachable".into());
+        assert_eq!(timestamper.broken_sources(), vec![id]);
+
+        let now = Instant::now();
+        assert!(!poll_due(&mut timestamper.broken, id, now));
+        assert!(poll_due(
+            &mut timestamper.broken,
+            id,
+            now + BROKEN_SOURCE_RETRY_INTERVAL
+        ));
+
+        ts_tx.send(TimestampMessage::RetrySource(id)).unwrap();
+        timestamper.update_sources();
+        assert!(timestamper.broken_sources().is_empty());
+        assert!(poll_due(&mut timestamper.broken, id, now));
+    }
+
+    #[test]
+    fn parse_reordered_csv_record() {
+        let columns = CsvConsistencyColumns {
+            source_name: 2,
+            timestamp: 0,
+            offset: 3,
+        };
+        assert_eq!(
+            byo_parse_csv_ts_update(b"7,ignored,source,3", &columns).unwrap(),
+            ("source".into(), 7, 3)
+        );
+        assert!(byo_parse_csv_ts_update(b"7,ignored,source", &columns).is_err());
+        assert!(byo_parse_csv_ts_update(b"source,7,3", &columns).is_err());
+    }
+
+    #[test]
+    fn decompress_record() {
+        let record = b"source,1,2";
+
+        let mut encoder = libflate::gzip::Encoder::new(vec![]).unwrap();
+        std::io::Write::write_all(&mut encoder, record).unwrap();
+        let gzipped = encoder.finish().into_result().unwrap();
+        assert_eq!(
+            byo_decompress(&gzipped, ConsistencyCompression::Gzip).unwrap(),
+            &record[..]
+        );
+
+        let snappy = snap::Encoder::new().compress_vec(record).unwrap();
+        assert_eq!(
+            byo_decompress(&snappy, ConsistencyCompression::Snappy).unwrap(),
+            &record[..]
+        );
+
+        assert!(byo_decompress(record, ConsistencyCompression::Gzip).is_err());
+        assert!(byo_decompress(record, ConsistencyCompression::Snappy).is_err());
+        assert_eq!(
+            byo_decompress(record, ConsistencyCompression::None).unwrap(),
+            &record[..]
+        );
+    }
+
+    #[test]
+    fn reject_sources_over_limit() {
+        let (mut timestamper, ts_tx, coord_rx) = in_memory_timestamper();
+        timestamper.max_sources = Some(0);
+        let id = source_id();
+        ts_tx
+            .send(TimestampMessage::Add(
+                id,
+                kafka_connector(),
+                Consistency::RealTime,
+            ))
+            .unwrap();
+        timestamper.update_sources();
+        match coord_rx.try_recv() {
+            Ok(TimestampMessage::AddRejected(rejected, reason)) => {
+                assert_eq!(rejected, id);
+                assert_eq!(reason, "already timestamping 0 sources, the maximum is 0");
+            }
+            update => panic!("unexpected message {:?}", update),
+        }
+        assert!(timestamper.rt_sources.is_empty());
+    }
+
+    #[test]
+    fn complete_bounded_source_at_end() {
+        let (timestamper, _ts_tx, _coord_rx) = in_memory_timestamper();
+        let connector = KafkaSourceConnector {
+            bounded: true,
+            ..kafka_connector()
+        };
+        let mut cons = timestamper
+            .create_rt_connector(source_id(), connector.clone(), -1)
+            .unwrap();
+
+        // An empty topic has no end yet, even though the source caught up with it
+        cons.caught_up = true;
+        cons.observe_high_watermark(-1);
+        assert!(!cons.is_complete());
+
+        // The end is the first high watermark, not the one the source catches up with
+        cons.observe_high_watermark(5);
+        cons.observe_high_watermark(9);
+        cons.last_offset = 3;
+        assert!(!cons.is_complete());
+        cons.last_offset = 5;
+        assert!(cons.is_complete());
+
+        // A configured end is not reached by catching up with an idle topic
+        let mut cons = timestamper
+            .create_rt_connector(
+                source_id(),
+                KafkaSourceConnector {
+                    end_offset: Some(10),
+                    ..connector.clone()
+                },
+                -1,
+            )
+            .unwrap();
+        cons.observe_high_watermark(5);
+        cons.last_offset = 5;
+        cons.caught_up = true;
+        assert!(!cons.is_complete());
+        cons.last_offset = 10;
+        assert!(cons.is_complete());
+
+        // A BYO source has no end until the watermarks of its topic are queried, however
+        // far its consistency topic has been read
+        let mut cons = timestamper
+            .create_byo_connector(source_id(), connector.clone(), "consistency".into(), None)
+            .unwrap();
+        assert!(cons.end_consumer.is_some());
+        cons.last_offset = 100;
+        assert!(!cons.is_complete());
+
+        // With a configured end, it is complete once its timestamps reach the end
+        let mut cons = timestamper
+            .create_byo_connector(
+                source_id(),
+                KafkaSourceConnector {
+                    end_offset: Some(10),
+                    ..connector
+                },
+                "consistency".into(),
+                None,
+            )
+            .unwrap();
+        assert!(cons.end_consumer.is_none());
+        cons.last_offset = 5;
+        assert!(!cons.is_complete());
+        cons.last_offset = 10;
+        assert!(cons.is_complete());
+    }
+
+    #[test]
+    fn persist_paused_source() {
+        let storage = Arc::new(Mutex::new(catalog::sql::Connection::open(None).unwrap()));
+        let id = source_id();
+
+        let (mut timestamper, ts_tx, _coord_rx) = timestamper_with_storage(storage.clone());
+        assert!(!timestamper.persisted_paused(id));
+        ts_tx.send(TimestampMessage::Pause(id)).unwrap();
+        timestamper.update_sources();
+        drop(timestamper);
+
+        let (mut timestamper, ts_tx, _coord_rx) = timestamper_with_storage(storage);
+        assert!(timestamper.persisted_paused(id));
+        ts_tx.send(TimestampMessage::Resume(id)).unwrap();
+        timestamper.update_sources();
+        assert!(!timestamper.persisted_paused(id));
+    }
+
+    #[test]
+    fn pull_mode_handles_messages_before_tick() {
+        let storage = Arc::new(Mutex::new(catalog::sql::Connection::open(None).unwrap()));
+        let config = TimestampConfig::builder().pull(true).build();
+        let (mut timestamper, ts_tx, _coord_rx) = timestamper_with_config(&config, storage);
+        let id = source_id();
+
+        // Control messages take effect without waiting for a Tick
+        ts_tx.send(TimestampMessage::Pause(id)).unwrap();
+        timestamper.wait_for_message();
+        timestamper.update_sources();
+        assert!(timestamper.persisted_paused(id));
+        assert!(!timestamper.tick_requested);
+
+        ts_tx.send(TimestampMessage::Tick).unwrap();
+        timestamper.wait_for_message();
+        timestamper.update_sources();
+        assert!(timestamper.tick_requested);
+
+        ts_tx.send(TimestampMessage::Shutdown).unwrap();
+        timestamper.wait_for_message();
+        assert!(timestamper.update_sources());
+    }
+
+    #[test]
+    fn select_record_format_from_header() {
+        let message = |format: Option<&str>| ConsistencyMessage {
+            key: None,
+            payload: vec![],
+            offset: 0,
+            timestamp: None,
+            format: format.map(|format| format.as_bytes().to_vec()),
+        };
+        let mut connector = kafka_connector();
+        let columns = CsvConsistencyColumns {
+            source_name: 2,
+            timestamp: 0,
+            offset: 1,
+        };
+        connector.consistency_format = ConsistencyFormat::Csv(columns.clone());
+        connector.consistency_format_header = Some("format".into());
+
+        let format = |format| {
+            byo_record_format(&message(format), &connector)
+                .map(|format| format.into_owned())
+                .map_err(|e| e.to_string())
+        };
+        assert_eq!(format(None), Ok(ConsistencyFormat::Csv(columns.clone())));
+        assert_eq!(format(Some("CSV")), Ok(ConsistencyFormat::Csv(columns)));
+        assert_eq!(
+            format(Some("json")),
+            Ok(ConsistencyFormat::Json(JsonConsistencyFields::default()))
+        );
+        assert_eq!(
+            format(Some("debezium_transaction")),
+            Ok(ConsistencyFormat::DebeziumTransaction)
+        );
+        assert_eq!(
+            format(Some("avro")),
+            Err("unknown consistency format avro".to_owned())
+        );
+    }
+
+    #[test]
+    fn reject_offsets_going_backwards() {
+        let (accepted, errors) =
+            byo_check_offset_order(vec![(10, 5), (20, 3), (30, 8), (40, 8), (50, 7), (60, 9)]);
+        assert_eq!(accepted, vec![(10, 5), (30, 8), (40, 8), (60, 9)]);
+        let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "offset 3 at timestamp 20 is behind offset 5 earlier in the batch",
+                "offset 7 at timestamp 50 is behind offset 8 earlier in the batch",
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_debezium_transaction() {
+        assert_eq!(
+            byo_parse_debezium_transaction(br#"{"status": "BEGIN", "id": "571"}"#).unwrap(),
+            None
+        );
+        let end = br#"{"payload": {"status": "END", "id": "571", "event_count": 3,
+            "data_collections": [
+                {"data_collection": "inventory.customers", "event_count": 2},
+                {"data_collection": "inventory.orders", "event_count": 1}
+            ]}}"#;
+        assert_eq!(
+            byo_parse_debezium_transaction(end).unwrap(),
+            Some(DebeziumTransaction {
+                ts_ms: None,
+                data_collections: vec![
+                    ("inventory.customers".into(), 2),
+                    ("inventory.orders".into(), 1)
+                ],
+            })
+        );
+        assert!(byo_parse_debezium_transaction(
+            br#"{"status": "END", "data_collections": [{"data_collection": "t"}]}"#
+        )
+        .is_err());
+
+        assert!(debezium_collection_matches(
+            "dbserver1.inventory.customers",
+            "inventory.customers"
+        ));
+        assert!(debezium_collection_matches(
+            "inventory.customers",
+            "inventory.customers"
+        ));
+        assert!(!debezium_collection_matches(
+            "dbserver1.inventory.old_customers",
+            "customers"
+        ));
+    }
+
+    #[test]
+    fn parse_json_record() {
+        let fields = JsonConsistencyFields {
+            source_name: "/source".into(),
+            timestamp: "/event_ts".into(),
+            offset: "/position/log_offset".into(),
+        };
+        assert_eq!(
+            byo_parse_json_ts_update(
+                br#"{"source": "s", "event_ts": 7, "position": {"log_offset": 3}}"#,
+                &fields
+            )
+            .unwrap(),
+            ("s".into(), 7, 3)
+        );
+        let err =
+            byo_parse_json_ts_update(br#"{"source": "s", "event_ts": 7}"#, &fields).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "missing required field /position/log_offset"
+        );
+        assert!(byo_parse_json_ts_update(
+            br#"{"source": "s", "event_ts": -7, "position": {"log_offset": 3}}"#,
+            &fields
+        )
+        .is_err());
     }
 }
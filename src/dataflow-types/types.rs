@@ -403,13 +403,154 @@ pub enum Consistency {
     RealTime,
 }
 
+/// The format of the records of a consistency topic.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ConsistencyFormat {
+    /// CSV records made of a source name, a timestamp and an offset.
+    Csv(CsvConsistencyColumns),
+    /// JSON objects whose fields are located by JSON pointers.
+    Json(JsonConsistencyFields),
+    /// The JSON records of a Debezium transaction metadata topic. Offsets are
+    /// derived from the per-collection event counts of `END` records.
+    DebeziumTransaction,
+}
+
+/// The compression applied to each record of a consistency topic.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ConsistencyCompression {
+    None,
+    Gzip,
+    Snappy,
+}
+
+/// The indexes of the columns of a CSV consistency record. A record has
+/// exactly as many columns as needed to hold the highest index.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CsvConsistencyColumns {
+    pub source_name: usize,
+    pub timestamp: usize,
+    pub offset: usize,
+}
+
+impl Default for CsvConsistencyColumns {
+    /// The columns of records of the form `SourceName,TS,Offset`.
+    fn default() -> Self {
+        CsvConsistencyColumns {
+            source_name: 0,
+            timestamp: 1,
+            offset: 2,
+        }
+    }
+}
+
+/// The JSON pointers (RFC 6901) at which the values of a JSON consistency
+/// record are found.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct JsonConsistencyFields {
+    pub source_name: String,
+    pub timestamp: String,
+    pub offset: String,
+}
+
+impl Default for JsonConsistencyFields {
+    /// The fields of records of the form
+    /// `{"source_name": ..., "timestamp": ..., "offset": ...}`.
+    fn default() -> Self {
+        JsonConsistencyFields {
+            source_name: "/source_name".into(),
+            timestamp: "/timestamp".into(),
+            offset: "/offset".into(),
+        }
+    }
+}
+
+/// The unit of the timestamps found in a consistency topic.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum TimestampUnit {
+    Seconds,
+    Millis,
+    Micros,
+    Nanos,
+}
+
+impl TimestampUnit {
+    /// Converts a timestamp in this unit to milliseconds, the unit of
+    /// real-time timestamps. Returns `None` on overflow.
+    pub fn to_millis(self, ts: u64) -> Option<u64> {
+        match self {
+            TimestampUnit::Seconds => ts.checked_mul(1_000),
+            TimestampUnit::Millis => Some(ts),
+            TimestampUnit::Micros => Some(ts / 1_000),
+            TimestampUnit::Nanos => Some(ts / 1_000_000),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct KafkaSourceConnector {
     pub url: Url,
     pub topic: String,
     pub ssl_certificate_file: Option<PathBuf>,
+    /// Whether the consistency topic is log-compacted. Compacted consistency
+    /// topics are read up to their high watermark on every timestamping cycle.
+    pub consistency_compacted: bool,
+    /// The maximum number of messages that can be assigned the same timestamp.
+    /// Overrides the timestamper's global limit when set.
+    pub max_increment_ts_size: Option<i64>,
+    /// Whether the topic is bounded, i.e. will not receive new messages once
+    /// its end has been reached.
+    pub bounded: bool,
+    /// The offset of the last message of a bounded topic. Defaults to the
+    /// last message present when timestamping of the source starts.
+    pub end_offset: Option<i64>,
+    /// Whether only records of committed transactions are read from the
+    /// consistency topic.
+    pub consistency_read_committed: bool,
+    /// Whether the timestamps assigned to the source are persisted, so that
+    /// they can be recovered after a restart.
+    pub durable: bool,
+    /// The unit of the timestamps found in the consistency topic.
+    pub consistency_timestamp_unit: TimestampUnit,
+    /// The format of the records of the consistency topic.
+    pub consistency_format: ConsistencyFormat,
+    /// Whether real-time timestamps are taken from the Kafka timestamps of the
+    /// messages, rather than from the system clock.
+    pub event_time: bool,
+    /// The brokers of the cluster hosting the consistency topic, if different
+    /// from the cluster of the data topic.
+    pub consistency_url: Option<Url>,
+    /// The certificate used to connect to the consistency topic's cluster, if
+    /// different from the one of the data topic.
+    pub consistency_ssl_certificate_file: Option<PathBuf>,
+    /// Whether the source name of consistency records is read from the Kafka
+    /// key of their message, rather than from the record itself.
+    pub consistency_source_name_from_key: bool,
+    /// The compression of the records of the consistency topic.
+    pub consistency_compression: ConsistencyCompression,
+    /// A signed correction, in milliseconds, added to the timestamps of the
+    /// consistency topic, e.g. to align a producer whose clock is skewed.
+    pub consistency_timestamp_correction: i64,
+    /// The Kafka header whose value, one of `csv`, `json` or
+    /// `debezium_transaction`, selects the format of each consistency record,
+    /// if any. This lets sources of different formats share a consistency
+    /// topic. Records without the header are in `consistency_format`.
+    pub consistency_format_header: Option<String>,
+    /// Additional rdkafka properties of the timestamper's consumers. They are
+    /// applied after the consumers' own settings, so they take precedence over
+    /// them, except for the ones in `PROTECTED_KAFKA_OPTIONS`.
+    pub config_options: HashMap<String, String>,
 }
 
+/// The rdkafka properties that `KafkaSourceConnector::config_options` cannot
+/// set, as timestamping is only correct with the values the timestamper picks.
+pub const PROTECTED_KAFKA_OPTIONS: &[&str] = &[
+    "enable.auto.commit",
+    "group.id",
+    "enable.partition.eof",
+    "isolation.level",
+    "auto.offset.reset",
+];
+
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct KinesisSourceConnector {
     pub arn: String,
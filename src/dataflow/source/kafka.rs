@@ -54,6 +54,7 @@ where
         url,
         topic,
         ssl_certificate_file,
+        ..
     } = connector.clone();
 
     let ts = if read_kafka {
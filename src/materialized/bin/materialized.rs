@@ -146,6 +146,10 @@ fn run() -> Result<(), failure::Error> {
         bail!("process ID {} is not between 0 and {}", process, processes);
     }
 
+    if max_increment_ts_size <= 0 {
+        bail!("batch size must be positive, got {}", max_increment_ts_size);
+    }
+
     let addresses = match address_file {
         None => (0..processes)
             .map(|i| SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 6875 + i as u16))
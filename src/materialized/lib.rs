@@ -254,10 +254,12 @@ pub fn serve(mut config: Config) -> Result<Server, failure::Error> {
             logging: logging_config.as_ref(),
             data_directory: config.data_directory.as_deref(),
             timestamp: match config.timestamp_frequency {
-                Some(freq) => Some(coord::TimestampConfig {
-                    frequency: freq,
-                    max_size: config.max_increment_ts_size,
-                }),
+                Some(freq) => Some(
+                    coord::TimestampConfig::builder()
+                        .frequency(freq)
+                        .max_size(config.max_increment_ts_size)
+                        .build(),
+                ),
                 None => None,
             },
             executor: &executor,
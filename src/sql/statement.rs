@@ -14,7 +14,6 @@
 use itertools::join;
 use std::collections::{BTreeMap, HashMap};
 use std::iter;
-use std::path::PathBuf;
 
 use failure::{bail, format_err, ResultExt};
 use itertools::Itertools;
@@ -23,9 +22,11 @@ use url::Url;
 use catalog::names::{DatabaseSpecifier, FullName, PartialName};
 use catalog::{Catalog, CatalogItem, SchemaType};
 use dataflow_types::{
-    AvroEncoding, Consistency, CsvEncoding, DataEncoding, Envelope, ExternalSourceConnector,
-    FileSourceConnector, KafkaSinkConnector, KafkaSourceConnector, KinesisSourceConnector,
-    PeekWhen, ProtobufEncoding, RowSetFinishing, SinkConnector, SourceConnector,
+    AvroEncoding, Consistency, ConsistencyCompression, ConsistencyFormat, CsvConsistencyColumns,
+    CsvEncoding, DataEncoding, Envelope, ExternalSourceConnector, FileSourceConnector,
+    JsonConsistencyFields, KafkaSinkConnector, KafkaSourceConnector, KinesisSourceConnector,
+    PeekWhen, ProtobufEncoding, RowSetFinishing, SinkConnector, SourceConnector, TimestampUnit,
+    PROTECTED_KAFKA_OPTIONS,
 };
 use expr::GlobalId;
 use interchange::{avro, protobuf};
@@ -953,6 +954,16 @@ fn handle_create_dataflow_pure(
                         Some(Value::SingleQuotedString(topic)) => Consistency::BringYourOwn(topic),
                         Some(_) => bail!("consistency must be a string"),
                     };
+                    // The consistency_ options all configure the consistency topic
+                    if consistency == Consistency::RealTime {
+                        if let Some(name) = with_options
+                            .keys()
+                            .filter(|name| name.starts_with("consistency_"))
+                            .min()
+                        {
+                            bail!("{} requires a consistency topic", name);
+                        }
+                    }
 
                     let ssl_certificate_file = match with_options.remove("ssl_certificate_file") {
                         None => None,
@@ -960,21 +971,248 @@ fn handle_create_dataflow_pure(
                         Some(_) => bail!("ssl_certificate_file must be a string"),
                     };
 
+                    let consistency_compacted = match with_options.remove("consistency_compacted") {
+                        None => false,
+                        Some(Value::Boolean(b)) => b,
+                        Some(_) => bail!("consistency_compacted must be a boolean"),
+                    };
+
+                    let max_increment_ts_size = match with_options.remove("max_increment_ts_size") {
+                        None => None,
+                        Some(Value::Number(n)) => match n.parse::<i64>() {
+                            Ok(n) if n > 0 => Some(n),
+                            _ => bail!("max_increment_ts_size must be a positive integer"),
+                        },
+                        Some(_) => bail!("max_increment_ts_size must be a positive integer"),
+                    };
+
+                    let bounded = match with_options.remove("bounded") {
+                        None => false,
+                        Some(Value::Boolean(b)) => b,
+                        Some(_) => bail!("bounded must be a boolean"),
+                    };
+                    let end_offset = match with_options.remove("end_offset") {
+                        None => None,
+                        Some(Value::Number(n)) => match n.parse::<i64>() {
+                            Ok(n) if n >= 0 => Some(n),
+                            _ => bail!("end_offset must be a non-negative integer"),
+                        },
+                        Some(_) => bail!("end_offset must be a non-negative integer"),
+                    };
+                    if end_offset.is_some() && !bounded {
+                        bail!("end_offset requires a bounded source");
+                    }
+
+                    let consistency_read_committed =
+                        match with_options.remove("consistency_read_committed") {
+                            None => true,
+                            Some(Value::Boolean(b)) => b,
+                            Some(_) => bail!("consistency_read_committed must be a boolean"),
+                        };
+
+                    let durable = match with_options.remove("durable") {
+                        None => true,
+                        Some(Value::Boolean(b)) => b,
+                        Some(_) => bail!("durable must be a boolean"),
+                    };
+
+                    let consistency_timestamp_unit =
+                        match with_options.remove("consistency_timestamp_unit") {
+                            None => TimestampUnit::Millis,
+                            Some(Value::SingleQuotedString(unit)) => {
+                                match unit.to_ascii_lowercase().as_str() {
+                                    "seconds" => TimestampUnit::Seconds,
+                                    "milliseconds" => TimestampUnit::Millis,
+                                    "microseconds" => TimestampUnit::Micros,
+                                    "nanoseconds" => TimestampUnit::Nanos,
+                                    _ => bail!(
+                                        "consistency_timestamp_unit must be one of seconds, \
+                                         milliseconds, microseconds or nanoseconds"
+                                    ),
+                                }
+                            }
+                            Some(_) => bail!("consistency_timestamp_unit must be a string"),
+                        };
+
+                    let consistency_timestamp_correction =
+                        match with_options.remove("consistency_timestamp_correction") {
+                            None => 0,
+                            // Negative corrections can only be written as strings
+                            Some(Value::Number(n)) | Some(Value::SingleQuotedString(n)) => {
+                                match n.trim().parse::<i64>() {
+                                    Ok(n) => n,
+                                    Err(_) => bail!(
+                                        "consistency_timestamp_correction must be an integer \
+                                         number of milliseconds"
+                                    ),
+                                }
+                            }
+                            Some(_) => bail!(
+                                "consistency_timestamp_correction must be an integer number \
+                                 of milliseconds"
+                            ),
+                        };
+
+                    let mut json_field = |name: &str, default: &str| match with_options
+                        .remove(&format!("consistency_json_{}", name))
+                    {
+                        None => Ok(default.to_owned()),
+                        Some(Value::SingleQuotedString(pointer)) => Ok(pointer),
+                        Some(_) => Err(format_err!("consistency_json_{} must be a string", name)),
+                    };
+                    let default_fields = JsonConsistencyFields::default();
+                    let json_fields = JsonConsistencyFields {
+                        source_name: json_field("source_name", &default_fields.source_name)?,
+                        timestamp: json_field("timestamp", &default_fields.timestamp)?,
+                        offset: json_field("offset", &default_fields.offset)?,
+                    };
+                    let default_columns = CsvConsistencyColumns::default();
+                    let mut csv_column = |name: &str, default: usize| match with_options
+                        .remove(&format!("consistency_csv_{}", name))
+                    {
+                        None => Ok(default),
+                        Some(Value::Number(n)) => n.parse::<usize>().map_err(|_| {
+                            format_err!("consistency_csv_{} must be a non-negative integer", name)
+                        }),
+                        Some(_) => Err(format_err!(
+                            "consistency_csv_{} must be a non-negative integer",
+                            name
+                        )),
+                    };
+                    let csv_columns = CsvConsistencyColumns {
+                        source_name: csv_column("source_name", default_columns.source_name)?,
+                        timestamp: csv_column("timestamp", default_columns.timestamp)?,
+                        offset: csv_column("offset", default_columns.offset)?,
+                    };
+                    if csv_columns.source_name == csv_columns.timestamp
+                        || csv_columns.source_name == csv_columns.offset
+                        || csv_columns.timestamp == csv_columns.offset
+                    {
+                        bail!("consistency_csv columns must be distinct");
+                    }
+                    let consistency_format = match with_options.remove("consistency_format") {
+                        None => ConsistencyFormat::Csv(csv_columns),
+                        Some(Value::SingleQuotedString(format)) => {
+                            match format.to_ascii_lowercase().as_str() {
+                                "csv" => ConsistencyFormat::Csv(csv_columns),
+                                "json" => ConsistencyFormat::Json(json_fields),
+                                "debezium_transaction" => ConsistencyFormat::DebeziumTransaction,
+                                _ => bail!(
+                                    "consistency_format must be one of csv, json or debezium_transaction"
+                                ),
+                            }
+                        }
+                        Some(_) => bail!("consistency_format must be a string"),
+                    };
+
+                    let consistency_format_header =
+                        match with_options.remove("consistency_format_header") {
+                            None => None,
+                            Some(Value::SingleQuotedString(header)) => Some(header),
+                            Some(_) => bail!("consistency_format_header must be a string"),
+                        };
+
+                    let consistency_compression = match with_options
+                        .remove("consistency_compression")
+                    {
+                        None => ConsistencyCompression::None,
+                        Some(Value::SingleQuotedString(compression)) => {
+                            match compression.to_ascii_lowercase().as_str() {
+                                "none" => ConsistencyCompression::None,
+                                "gzip" => ConsistencyCompression::Gzip,
+                                "snappy" => ConsistencyCompression::Snappy,
+                                _ => bail!(
+                                    "consistency_compression must be one of none, gzip or snappy"
+                                ),
+                            }
+                        }
+                        Some(_) => bail!("consistency_compression must be a string"),
+                    };
+
+                    let event_time = match with_options.remove("event_time") {
+                        None => false,
+                        Some(Value::Boolean(b)) => b,
+                        Some(_) => bail!("event_time must be a boolean"),
+                    };
+                    if event_time && consistency != Consistency::RealTime {
+                        bail!("event_time requires real-time consistency");
+                    }
+
+                    let consistency_url = match with_options.remove("consistency_broker") {
+                        None => None,
+                        Some(Value::SingleQuotedString(mut broker)) => {
+                            if !broker.contains(':') {
+                                broker += ":9092";
+                            }
+                            Some(broker.parse()?)
+                        }
+                        Some(_) => bail!("consistency_broker must be a string"),
+                    };
+                    let consistency_ssl_certificate_file =
+                        match with_options.remove("consistency_ssl_certificate_file") {
+                            None => None,
+                            Some(Value::SingleQuotedString(p)) => Some(p.into()),
+                            Some(_) => bail!("consistency_ssl_certificate_file must be a string"),
+                        };
+                    let consistency_source_name_from_key =
+                        match with_options.remove("consistency_source_name_from_key") {
+                            None => false,
+                            Some(Value::Boolean(b)) => b,
+                            Some(_) => bail!("consistency_source_name_from_key must be a boolean"),
+                        };
+
+                    // Additional rdkafka properties, written as 'key=value,key=value'
+                    let mut config_options = HashMap::new();
+                    match with_options.remove("rdkafka_options") {
+                        None => (),
+                        Some(Value::SingleQuotedString(options)) => {
+                            for option in options.split(',').filter(|o| !o.trim().is_empty()) {
+                                let mut parts = option.splitn(2, '=');
+                                let key = parts.next().unwrap_or("").trim();
+                                let value = match parts.next() {
+                                    Some(value) if !key.is_empty() => value.trim(),
+                                    _ => bail!(
+                                        "rdkafka_options must be a list of key=value pairs, found {}",
+                                        option
+                                    ),
+                                };
+                                if PROTECTED_KAFKA_OPTIONS.contains(&key) {
+                                    bail!("rdkafka_options cannot set {}", key);
+                                }
+                                config_options.insert(key.to_owned(), value.to_owned());
+                            }
+                        }
+                        Some(_) => bail!("rdkafka_options must be a string"),
+                    }
+
                     if !with_options.is_empty() {
                         bail!(
                             "Unexpected WITH options: {}",
                             join(with_options.keys(), ",")
                         )
                     }
-                    let url = broker.parse()?;
-                    build_kafka_source(
-                        url,
-                        topic.clone(),
-                        format,
-                        envelope,
-                        consistency,
+                    let connector = KafkaSourceConnector {
+                        url: broker.parse()?,
+                        topic: topic.clone(),
                         ssl_certificate_file,
-                    )?
+                        consistency_compacted,
+                        max_increment_ts_size,
+                        bounded,
+                        end_offset,
+                        consistency_read_committed,
+                        durable,
+                        consistency_timestamp_unit,
+                        event_time,
+                        consistency_format,
+                        consistency_url,
+                        consistency_ssl_certificate_file,
+                        consistency_source_name_from_key,
+                        consistency_compression,
+                        consistency_timestamp_correction,
+                        consistency_format_header,
+                        config_options,
+                    };
+                    build_kafka_source(connector, format, envelope, consistency)?
                 }
                 Connector::Kinesis { arn, with_options } => {
                     let mut with_options: HashMap<_, _> = with_options
@@ -1404,16 +1642,14 @@ fn handle_query(
 }
 
 fn build_kafka_source(
-    url: Url,
-    topic: String,
+    connector: KafkaSourceConnector,
     format: &Format,
     envelope: Envelope,
     consistency: Consistency,
-    ssl_certificate_file: Option<PathBuf>,
 ) -> Result<Source, failure::Error> {
     match (format, envelope) {
         (Format::Avro(schema), Envelope::Debezium) => {
-            build_kafka_avro_source(schema, url, topic, consistency, ssl_certificate_file)
+            build_kafka_avro_source(schema, connector, consistency)
         }
         (Format::Avro(_), _) => {
             // TODO(brennan) -- there's no reason not to support this
@@ -1425,14 +1661,7 @@ fn build_kafka_source(
                 schema,
             },
             Envelope::None,
-        ) => build_kafka_protobuf_source(
-            schema,
-            url,
-            topic,
-            message_name,
-            consistency,
-            ssl_certificate_file,
-        ),
+        ) => build_kafka_protobuf_source(schema, connector, message_name, consistency),
         (Format::Protobuf { .. }, Envelope::Debezium) => {
             bail!("Currently, Debezium-style envelopes are not supported for protobuf messages.")
         }
@@ -1471,10 +1700,8 @@ async fn get_remote_avro_schema(url: Url, topic: String) -> Result<Schema, failu
 
 fn build_kafka_avro_source(
     schema: &AvroSchema,
-    kafka_url: Url,
-    topic: String,
+    connector: KafkaSourceConnector,
     consistency: Consistency,
-    ssl_certificate_file: Option<PathBuf>,
 ) -> Result<Source, failure::Error> {
     let Schema {
         key_schema,
@@ -1514,11 +1741,7 @@ fn build_kafka_avro_source(
     Ok(Source {
         create_sql: "<filled in later>".into(),
         connector: SourceConnector::External {
-            connector: ExternalSourceConnector::Kafka(KafkaSourceConnector {
-                url: kafka_url,
-                topic,
-                ssl_certificate_file,
-            }),
+            connector: ExternalSourceConnector::Kafka(connector),
             encoding: DataEncoding::Avro(AvroEncoding {
                 raw_schema: value_schema,
                 schema_registry_url,
@@ -1532,11 +1755,9 @@ fn build_kafka_avro_source(
 
 fn build_kafka_protobuf_source(
     schema: &sql_parser::ast::Schema,
-    url: Url,
-    topic: String,
+    connector: KafkaSourceConnector,
     message_name: &str,
     consistency: Consistency,
-    ssl_certificate_file: Option<PathBuf>,
 ) -> Result<Source, failure::Error> {
     let descriptors = match schema {
         sql_parser::ast::Schema::Inline(bytes) => strconv::parse_bytes(&bytes)?,
@@ -1551,11 +1772,7 @@ fn build_kafka_protobuf_source(
     Ok(Source {
         create_sql: "<filled in later>".into(),
         connector: SourceConnector::External {
-            connector: ExternalSourceConnector::Kafka(KafkaSourceConnector {
-                url,
-                topic,
-                ssl_certificate_file,
-            }),
+            connector: ExternalSourceConnector::Kafka(connector),
             encoding: DataEncoding::Protobuf(ProtobufEncoding {
                 descriptors,
                 message_name: message_name.to_owned(),
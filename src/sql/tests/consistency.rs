@@ -0,0 +1,56 @@
+// Copyright Materialize, Inc. All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+use std::error::Error;
+
+use catalog::{BincodeSerializer, Catalog};
+use ore::collections::CollectionExt;
+use repr::Row;
+use sql::{Params, Session};
+
+#[test]
+fn test_consistency_options_require_consistency_topic() -> Result<(), Box<dyn Error>> {
+    let catalog = Catalog::open::<BincodeSerializer, _>(None, |_| ())?;
+    let session = Session::default();
+    let params = Params {
+        datums: Row::pack(&[]),
+        types: vec![],
+    };
+    let test_cases = vec![
+        ("consistency_broker", "'localhost:9092'"),
+        ("consistency_ssl_certificate_file", "'/path/to/file'"),
+        ("consistency_compacted", "true"),
+        ("consistency_read_committed", "true"),
+        ("consistency_timestamp_unit", "'seconds'"),
+        ("consistency_timestamp_correction", "1"),
+        ("consistency_format", "'json'"),
+        ("consistency_format_header", "'header'"),
+        ("consistency_compression", "'gzip'"),
+        ("consistency_source_name_from_key", "true"),
+        ("consistency_json_topic", "'topic'"),
+        ("consistency_csv_topic", "0"),
+    ];
+    for (name, value) in test_cases {
+        let sql = format!(
+            "CREATE SOURCE s FROM KAFKA BROKER 'localhost:9092' TOPIC 't' \
+             WITH ({} = {}) FORMAT BYTES",
+            name, value
+        );
+        println!("> {}", sql);
+        let stmt = sql::parse(sql)?.into_element();
+        match futures::executor::block_on(sql::plan(&catalog, &session, stmt, &params)) {
+            Ok(_) => panic!("planning succeeded with {} on a real-time source", name),
+            Err(e) => assert_eq!(
+                e.to_string(),
+                format!("{} requires a consistency topic", name)
+            ),
+        }
+    }
+    Ok(())
+}